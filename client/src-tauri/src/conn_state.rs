@@ -0,0 +1,168 @@
+//! Shared connection-state detection from helper-process stdout/stderr.
+//!
+//! Readiness used to be decided two different ways depending on the path:
+//! a flat `thread::sleep` before a single `try_wait` (the old DNSTT sing-box
+//! check), or a TCP/tun-interface probe polled on an interval
+//! (`wait_for_ready`). Both ignore what the helper is actually saying on its
+//! own stdout/stderr — a process can be alive and polling green on a probe
+//! while its own logs are reporting the tunnel failed to come up.
+//! `ReadyPatterns` gives each helper's known "tunnel is up" / "tunnel
+//! failed" log lines, and `StateWatcher` turns those into a
+//! `Connecting -> Connected -> Disconnected/Failed` state machine emitted to
+//! the frontend as `vpn-state` the moment a line resolves the attempt,
+//! instead of after a fixed wait.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnState {
+    Connecting,
+    Connected,
+    Disconnected,
+    Failed,
+}
+
+impl ConnState {
+    fn from_u8(v: u8) -> ConnState {
+        match v {
+            1 => ConnState::Connected,
+            2 => ConnState::Disconnected,
+            3 => ConnState::Failed,
+            _ => ConnState::Connecting,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct ConnStateEvent {
+    component: &'static str,
+    state: ConnState,
+    detail: String,
+}
+
+/// Per-helper substrings that mark a log line as the tunnel becoming ready
+/// or fatally failing. Each inner slice is a set of substrings that must
+/// ALL appear in the line — plain substring matching is enough since none
+/// of these tools version their log wording.
+pub struct ReadyPatterns {
+    pub ready: &'static [&'static [&'static str]],
+    pub fatal: &'static [&'static [&'static str]],
+}
+
+impl ReadyPatterns {
+    pub const SING_BOX: ReadyPatterns = ReadyPatterns {
+        ready: &[&["sing-box started"]],
+        fatal: &[&["FATAL"], &["panic:"]],
+    };
+    pub const XRAY: ReadyPatterns = ReadyPatterns {
+        ready: &[&["Xray", "started"]],
+        fatal: &[&["Failed to start"]],
+    };
+    pub const DNSTT: ReadyPatterns = ReadyPatterns {
+        ready: &[&["listening"]],
+        fatal: &[&["permission denied"], &["address already in use"]],
+    };
+    pub const STRONGSWAN: ReadyPatterns = ReadyPatterns {
+        ready: &[&["CHILD_SA", "established"]],
+        fatal: &[&["establishing CHILD_SA failed"], &["establishing IKE_SA failed"]],
+    };
+    pub const PPPD: ReadyPatterns = ReadyPatterns {
+        ready: &[&["local  IP address"]],
+        fatal: &[&["Connection terminated"], &["LCP terminated"]],
+    };
+}
+
+fn line_matches(line: &str, needle_sets: &[&[&str]]) -> bool {
+    needle_sets.iter().any(|needles| needles.iter().all(|n| line.contains(n)))
+}
+
+/// Tracks one helper's connection attempt and emits every transition to the
+/// frontend as `vpn-state`. Cheap to clone — all clones (one per
+/// stdout/stderr reader thread) share the same underlying state, so only the
+/// first ready/fatal line across either stream resolves the attempt.
+#[derive(Clone)]
+pub struct StateWatcher {
+    app: tauri::AppHandle,
+    component: &'static str,
+    state: Arc<AtomicU8>,
+}
+
+impl StateWatcher {
+    pub fn new(app: tauri::AppHandle, component: &'static str) -> Self {
+        StateWatcher { app, component, state: Arc::new(AtomicU8::new(0)) }
+    }
+
+    pub fn state(&self) -> ConnState {
+        ConnState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    /// Back to `Connecting` ahead of a supervised restart, so a later log
+    /// line from the respawned process can resolve a new attempt.
+    pub fn reset(&self) {
+        self.state.store(ConnState::Connecting as u8, Ordering::SeqCst);
+    }
+
+    fn transition(&self, state: ConnState, detail: &str) {
+        self.state.store(state as u8, Ordering::SeqCst);
+        use tauri::Emitter;
+        let _ = self.app.emit(
+            "vpn-state",
+            ConnStateEvent { component: self.component, state, detail: detail.to_string() },
+        );
+    }
+
+    /// Feed one line of helper stdout/stderr. Only the first ready or fatal
+    /// line matters — once the attempt has resolved, later lines (restart
+    /// chatter, unrelated warnings) are left alone until `reset`.
+    pub fn feed_line(&self, line: &str, patterns: &ReadyPatterns) {
+        if self.state() != ConnState::Connecting {
+            return;
+        }
+        if line_matches(line, patterns.fatal) {
+            self.transition(ConnState::Failed, line);
+        } else if line_matches(line, patterns.ready) {
+            self.transition(ConnState::Connected, line);
+        }
+    }
+
+    pub fn mark_disconnected(&self) {
+        self.transition(ConnState::Disconnected, "process exited");
+    }
+}
+
+/// Wait for `watcher` to resolve from a log line, or for `fallback_probe` to
+/// succeed, whichever comes first — log lines are usually faster and more
+/// specific, but a helper's exact wording isn't guaranteed to match, so the
+/// existing TCP/tun/socket probe stays as a safety net rather than being
+/// replaced outright. Bails immediately if `child` has already exited, and
+/// times out after `timeout`.
+pub fn await_ready(
+    child: &mut std::process::Child,
+    watcher: &StateWatcher,
+    timeout: Duration,
+    mut fallback_probe: impl FnMut() -> bool,
+) -> Result<(), String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("process exited before becoming ready: {}", status));
+        }
+        match watcher.state() {
+            ConnState::Connected => return Ok(()),
+            ConnState::Failed => return Err("helper process reported a fatal error".to_string()),
+            _ => {}
+        }
+        if fallback_probe() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("timed out after {:?} waiting for readiness", timeout));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,105 @@
+//! Exit-country resolution via bundled MaxMind GeoIP lookup.
+//!
+//! `get_network_stats` used to hardcode `"countryCode": "??"` — there was no
+//! way for the UI to show where traffic actually exits. `resolve` fetches
+//! the tunnel's public egress IP by issuing a plain HTTP request through the
+//! already-running local SOCKS proxy (the same "enough of SOCKS5 to tunnel
+//! one request" handshake `latency::measure_proxy_delay` uses), then looks
+//! that IP up in a bundled `.mmdb` database via the `maxminddb` crate. The
+//! result is cached per session — `cached()` is what the stats sampler
+//! reads on every tick — and `reset()` clears that cache on every new
+//! connection, since the exit node can change between sessions.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const IP_ECHO_HOST: &str = "api.ipify.org";
+const IP_ECHO_PATH: &str = "/";
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn cached_country() -> &'static Mutex<String> {
+    static CACHE: OnceLock<Mutex<String>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new("??".to_string()))
+}
+
+/// The last resolved exit country, or `"??"` if nothing has resolved yet
+/// this session. Safe to call as often as the stats sampler likes — unlike
+/// `resolve`, it never touches the network.
+pub fn cached() -> String {
+    cached_country().lock().unwrap().clone()
+}
+
+/// Drop the cached country. Called from every protocol driver's
+/// post-connect hook so a reconnect re-resolves instead of reporting the
+/// previous session's exit node.
+pub fn reset() {
+    *cached_country().lock().unwrap() = "??".to_string();
+}
+
+fn mmdb_path(resource_dir: &std::path::Path) -> std::path::PathBuf {
+    let p1 = resource_dir.join("geoip").join("GeoLite2-Country.mmdb");
+    let p2 = resource_dir.join("resources").join("geoip").join("GeoLite2-Country.mmdb");
+    if p1.exists() { p1 } else { p2 }
+}
+
+/// Fetch the tunnel's public egress IP through the local SOCKS proxy and
+/// resolve it to an ISO country code, caching the result. Returns `Err`
+/// (and leaves the cache untouched) when the probe endpoint is unreachable,
+/// the tunnel is down, or the bundled database is missing;
+/// `resolve_exit_country` turns that into `"??"` for the UI.
+pub fn resolve(proxy_host: &str, proxy_port: u16, resource_dir: &std::path::Path) -> Result<String, String> {
+    let ip = fetch_exit_ip(proxy_host, proxy_port)?;
+    let code = lookup_country(&ip, resource_dir)?;
+    *cached_country().lock().unwrap() = code.clone();
+    Ok(code)
+}
+
+fn lookup_country(ip: &str, resource_dir: &std::path::Path) -> Result<String, String> {
+    let db_path = mmdb_path(resource_dir);
+    let reader = maxminddb::Reader::open_readfile(&db_path)
+        .map_err(|e| format!("Failed to open GeoIP database at {}: {}", db_path.display(), e))?;
+    let ip_addr: IpAddr = ip.parse().map_err(|e| format!("Bad IP from echo endpoint: {}", e))?;
+    let country: maxminddb::geoip2::Country = reader
+        .lookup(ip_addr)
+        .map_err(|e| format!("GeoIP lookup failed for {}: {}", ip, e))?;
+    Ok(country
+        .country
+        .and_then(|c| c.iso_code)
+        .unwrap_or("??")
+        .to_string())
+}
+
+/// A plain HTTP GET to an IP-echo endpoint, tunnelled through the local
+/// SOCKS proxy the same way `latency::measure_proxy_delay` reaches its probe
+/// endpoint — the response body is the caller's public IP as plain text.
+fn fetch_exit_ip(proxy_host: &str, proxy_port: u16) -> Result<String, String> {
+    let proxy_addr = (proxy_host, proxy_port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Could not resolve proxy {}:{}: {}", proxy_host, proxy_port, e))?
+        .next()
+        .ok_or_else(|| "No address found for proxy".to_string())?;
+
+    let mut stream = TcpStream::connect_timeout(&proxy_addr, FETCH_TIMEOUT)
+        .map_err(|e| format!("Failed to connect to local proxy {}:{}: {}", proxy_host, proxy_port, e))?;
+    stream.set_read_timeout(Some(FETCH_TIMEOUT)).ok();
+    stream.set_write_timeout(Some(FETCH_TIMEOUT)).ok();
+
+    crate::latency::socks5_connect(&mut stream, IP_ECHO_HOST, 80)?;
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        IP_ECHO_PATH, IP_ECHO_HOST
+    );
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).map_err(|e| e.to_string())?;
+    let response = String::from_utf8_lossy(&response);
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or("").trim();
+    if body.is_empty() {
+        return Err("Empty response from IP-echo endpoint".to_string());
+    }
+    Ok(body.to_string())
+}
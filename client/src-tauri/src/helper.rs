@@ -0,0 +1,477 @@
+//! Installable privileged helper, so the GUI stops re-elevating through
+//! UAC/`pkexec` every time it needs to do something privileged (bring up a
+//! TUN device, install kill switch firewall rules, ...).
+//!
+//! `restart_as_admin` re-launches the *entire* app through UAC/`pkexec`
+//! every time, which is disruptive and throws away window state. This
+//! module installs a small long-running privileged service instead: a
+//! systemd unit on Linux (`candyconnect-helper.service`, plus the
+//! `candyconnect.target` it's `WantedBy`, so future per-profile
+//! `candyconnect@.service` instances can group under the same target), or
+//! a Windows Service on Windows. `is_admin` reports whether that service is
+//! installed, not whether the current process happens to be elevated.
+//!
+//! `serve`/the `dispatch`/`ping` client calls are the two ends of the local,
+//! token-authenticated IPC channel the GUI uses instead of shelling out to
+//! `sudo`/triggering UAC directly. On Linux this is a Unix domain socket
+//! under `/run/candyconnect/`, whose filesystem permissions are the actual
+//! access control — reachability isn't "any local process", it's "the uid
+//! that installed the helper, or root". On Windows there's no equivalent to
+//! a Unix socket's ownership bits on a loopback TCP port, so the shared
+//! token is the only access control there; `install` captures the
+//! installing user's name and threads it through to `serve` (as a CLI arg,
+//! since the service runs as LocalSystem and can't otherwise learn who
+//! installed it) so the token's ACL grants exactly that account instead of
+//! guessing from `serve`'s own (LocalSystem) environment.
+//!
+//! `KillSwitchEngage`/`KillSwitchDisengage` are the first real privileged
+//! operations dispatched over this channel; `start_vpn`/`stop_vpn`'s own
+//! TUN/process bring-up is still done directly and is follow-up work, one
+//! driver at a time the same way the rest of this crate's protocol drivers
+//! were added.
+
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Passed on the command line to tell `run()` to act as the helper service
+/// instead of launching the GUI — what a systemd unit's `ExecStart=`/the
+/// Windows Service's `binPath=` actually invokes.
+pub const HELPER_ARG: &str = "--helper";
+
+#[cfg(target_os = "windows")]
+/// Loopback-only; nothing here is meant to be reachable off-box. Windows has
+/// no direct equivalent of a Unix socket's filesystem-permission scoping, so
+/// the token (see module doc comment) is what actually gates this.
+const IPC_ADDR: &str = "127.0.0.1:47851";
+
+#[cfg(target_os = "linux")]
+/// `/run` is a tmpfs recreated on boot, same as the rest of this crate's
+/// runtime state (e.g. `vici::VICI_SOCKET` lives under `/run/ipsec`).
+const SOCKET_DIR: &str = "/run/candyconnect";
+#[cfg(target_os = "linux")]
+const SOCKET_PATH: &str = "/run/candyconnect/helper.sock";
+
+#[derive(Serialize, Deserialize)]
+enum HelperRequest {
+    Ping,
+    KillSwitchEngage { server: String, app_data_dir: String },
+    KillSwitchDisengage,
+}
+
+#[derive(Serialize, Deserialize)]
+enum HelperResponse {
+    Pong,
+    Ok,
+    Err(String),
+}
+
+fn token_path() -> std::path::PathBuf {
+    #[cfg(target_os = "linux")]
+    {
+        std::path::Path::new(SOCKET_DIR).join("helper.token")
+    }
+    #[cfg(target_os = "windows")]
+    {
+        windows_shared_dir().join("helper.token")
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn windows_shared_dir() -> std::path::PathBuf {
+    // `ProgramData` resolves to the same path for every account on the
+    // machine, unlike `%TEMP%`/`USERNAME`, which differ between the
+    // LocalSystem service and the interactive user running the GUI — the
+    // mismatch that made the old per-process token location unreadable by
+    // one side or the other.
+    let root = std::env::var("ProgramData").unwrap_or_else(|_| "C:\\ProgramData".to_string());
+    std::path::PathBuf::from(root).join("CandyConnect")
+}
+
+fn random_token() -> String {
+    use rand::RngCore;
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A fresh CSPRNG token, written to `token_path()` and scoped to `owner` —
+/// the uid (Linux) or account name (Windows) captured from the installing
+/// user at `install()` time, not derived from the helper process's own
+/// (LocalSystem/root) environment. Regenerated every time the helper
+/// starts, so a stale token from a previous run never authenticates.
+#[cfg(target_os = "linux")]
+fn write_fresh_token(owner_uid: libc::uid_t) -> std::io::Result<String> {
+    use std::os::unix::fs::PermissionsExt;
+    let token = random_token();
+    std::fs::write(token_path(), &token)?;
+    std::fs::set_permissions(token_path(), std::fs::Permissions::from_mode(0o600))?;
+    chown_to(&token_path(), owner_uid);
+    Ok(token)
+}
+
+#[cfg(target_os = "windows")]
+fn write_fresh_token(owner: &str) -> std::io::Result<String> {
+    std::fs::create_dir_all(windows_shared_dir())?;
+    let token = random_token();
+    std::fs::write(token_path(), &token)?;
+    harden_windows_token_acl(owner);
+    Ok(token)
+}
+
+/// `chown(2)` `path` to `uid`, leaving the group untouched (`(gid_t)-1`).
+/// Best-effort — this runs from the root-owned helper service, so a failure
+/// here just means the owning user won't be able to use the socket/token,
+/// not a reason to bring the helper down.
+#[cfg(target_os = "linux")]
+fn chown_to(path: &std::path::Path, uid: libc::uid_t) {
+    use std::ffi::CString;
+    let Some(path_str) = path.to_str() else { return };
+    if let Ok(c_path) = CString::new(path_str) {
+        unsafe {
+            libc::chown(c_path.as_ptr(), uid, libc::gid_t::MAX);
+        }
+    }
+}
+
+/// Strip inherited ACEs from the token file and grant access only to
+/// `owner` — the installing user's account name, captured by `install()`
+/// and passed down through the service's `binPath=` (see module doc
+/// comment), not read from `serve`'s own `USERNAME`, which is wrong once
+/// the helper is running as LocalSystem.
+#[cfg(target_os = "windows")]
+fn harden_windows_token_acl(owner: &str) {
+    use std::os::windows::process::CommandExt;
+    let _ = std::process::Command::new("icacls")
+        .arg(token_path())
+        .args(&["/inheritance:r", "/grant:r", &format!("{}:F", owner)])
+        .creation_flags(0x08000000)
+        .output();
+}
+
+fn handle_request(request: HelperRequest) -> HelperResponse {
+    match request {
+        HelperRequest::Ping => HelperResponse::Pong,
+        HelperRequest::KillSwitchEngage { server, app_data_dir } => {
+            match crate::killswitch::engage(&server, std::path::Path::new(&app_data_dir)) {
+                Ok(()) => HelperResponse::Ok,
+                Err(e) => HelperResponse::Err(e),
+            }
+        }
+        HelperRequest::KillSwitchDisengage => match crate::killswitch::disengage() {
+            Ok(()) => HelperResponse::Ok,
+            Err(e) => HelperResponse::Err(e),
+        },
+    }
+}
+
+fn handle_connection<S: Read + Write>(mut stream: S, expected_token: &str) -> std::io::Result<()> {
+    let mut line = String::new();
+    BufReader::new(&mut stream).read_line(&mut line)?;
+    let (token, payload) = line.trim_end().split_once(' ').unwrap_or((line.trim_end(), ""));
+    if token != expected_token {
+        return Ok(());
+    }
+
+    let Ok(request) = serde_json::from_str::<HelperRequest>(payload) else { return Ok(()) };
+    let response = handle_request(request);
+    let body = serde_json::to_string(&response).unwrap_or_default();
+    stream.write_all(format!("{}\n", body).as_bytes())
+}
+
+/// The helper's own entry point — `run()` calls this instead of building
+/// the Tauri app when launched with `HELPER_ARG`. Blocks forever serving
+/// requests; systemd/the Windows Service Control Manager is what's
+/// responsible for restarting it if it exits.
+pub fn serve() -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    {
+        serve_unix()
+    }
+    #[cfg(target_os = "windows")]
+    {
+        serve_tcp()
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    {
+        Err("Privileged helper is not supported on this platform yet".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn owner_uid_from_env() -> libc::uid_t {
+    std::env::var("CANDYCONNECT_OWNER_UID")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| unsafe { libc::getuid() })
+}
+
+#[cfg(target_os = "linux")]
+fn serve_unix() -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    let owner_uid = owner_uid_from_env();
+
+    std::fs::create_dir_all(SOCKET_DIR).map_err(|e| format!("Failed to create {}: {}", SOCKET_DIR, e))?;
+    // Traversable by anyone (`--x`) so the owning uid can reach the socket
+    // file inside, but not listable/writable — the socket and token files
+    // themselves, chowned below, are what actually grant access.
+    let _ = std::fs::set_permissions(SOCKET_DIR, std::fs::Permissions::from_mode(0o711));
+
+    // A stale socket from a previous run would otherwise make `bind` fail.
+    let _ = std::fs::remove_file(SOCKET_PATH);
+    let token = write_fresh_token(owner_uid).map_err(|e| format!("Failed to write helper token: {}", e))?;
+
+    let listener = UnixListener::bind(SOCKET_PATH).map_err(|e| format!("Failed to bind helper socket: {}", e))?;
+    chown_to(std::path::Path::new(SOCKET_PATH), owner_uid);
+    let _ = std::fs::set_permissions(SOCKET_PATH, std::fs::Permissions::from_mode(0o600));
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let token = token.clone();
+        std::thread::spawn(move || {
+            let _ = handle_connection(stream, &token);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn serve_tcp() -> Result<(), String> {
+    use std::net::TcpListener;
+
+    let owner = std::env::args()
+        .collect::<Vec<_>>()
+        .windows(2)
+        .find(|pair| pair[0] == "--owner")
+        .map(|pair| pair[1].clone())
+        .unwrap_or_default();
+
+    let token = write_fresh_token(&owner).map_err(|e| format!("Failed to write helper token: {}", e))?;
+    let listener = TcpListener::bind(IPC_ADDR).map_err(|e| format!("Failed to bind helper socket: {}", e))?;
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let token = token.clone();
+        std::thread::spawn(move || {
+            let _ = handle_connection(stream, &token);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn connect() -> Result<std::os::unix::net::UnixStream, String> {
+    std::os::unix::net::UnixStream::connect(SOCKET_PATH).map_err(|e| format!("Helper not reachable: {}", e))
+}
+
+#[cfg(target_os = "windows")]
+fn connect() -> Result<std::net::TcpStream, String> {
+    std::net::TcpStream::connect(IPC_ADDR).map_err(|e| format!("Helper not reachable: {}", e))
+}
+
+fn dispatch(request: HelperRequest) -> Result<(), String> {
+    let token = std::fs::read_to_string(token_path()).map_err(|e| format!("Helper token not found: {}", e))?;
+    let mut stream = connect()?;
+
+    let payload = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+    stream.write_all(format!("{} {}\n", token.trim(), payload).as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    match serde_json::from_str::<HelperResponse>(line.trim_end()).map_err(|e| format!("Malformed helper response: {}", e))? {
+        HelperResponse::Ok | HelperResponse::Pong => Ok(()),
+        HelperResponse::Err(e) => Err(e),
+    }
+}
+
+/// Ping the running helper, for the GUI to confirm it's actually reachable
+/// right after `install`.
+#[allow(dead_code)]
+pub fn ping() -> Result<(), String> {
+    dispatch(HelperRequest::Ping)
+}
+
+/// Engage the kill switch through the privileged helper instead of shelling
+/// out to `sudo`/triggering UAC directly. Callers should check
+/// `is_installed()` first and fall back to `killswitch::engage` when the
+/// helper isn't set up.
+pub fn engage_kill_switch(server: &str, app_data_dir: &std::path::Path) -> Result<(), String> {
+    dispatch(HelperRequest::KillSwitchEngage {
+        server: server.to_string(),
+        app_data_dir: app_data_dir.display().to_string(),
+    })
+}
+
+/// Disengage the kill switch through the privileged helper — see
+/// `engage_kill_switch`.
+pub fn disengage_kill_switch() -> Result<(), String> {
+    dispatch(HelperRequest::KillSwitchDisengage)
+}
+
+/// Install and start the privileged helper service.
+pub fn install() -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    platform_install(&exe)
+}
+
+/// Stop and remove the privileged helper service.
+pub fn uninstall() -> Result<(), String> {
+    platform_uninstall()
+}
+
+/// Whether the helper service is currently installed — what `is_admin` now
+/// reports, in place of the current process's own elevation.
+pub fn is_installed() -> bool {
+    platform_is_installed()
+}
+
+#[cfg(target_os = "linux")]
+const UNIT_PATH: &str = "/etc/systemd/system/candyconnect-helper.service";
+#[cfg(target_os = "linux")]
+const TARGET_PATH: &str = "/etc/systemd/system/candyconnect.target";
+
+#[cfg(target_os = "linux")]
+fn platform_install(exe: &std::path::Path) -> Result<(), String> {
+    // `install()` runs in the interactive GUI process, not the eventual
+    // root-run service, so this is the one place that can correctly observe
+    // who's installing the helper — threaded through as an `Environment=`
+    // line since the service can't otherwise learn it from its own (root)
+    // context.
+    let owner_uid = unsafe { libc::getuid() };
+    let unit = format!(
+        "[Unit]\nDescription=CandyConnect privileged helper\nPartOf=candyconnect.target\n\n[Service]\nType=simple\nEnvironment=CANDYCONNECT_OWNER_UID={}\nExecStart={} {}\nRestart=on-failure\n\n[Install]\nWantedBy=candyconnect.target\n",
+        owner_uid,
+        exe.display(),
+        HELPER_ARG
+    );
+    let target = "[Unit]\nDescription=CandyConnect helper services\n\n[Install]\nWantedBy=multi-user.target\n";
+
+    write_privileged_file(UNIT_PATH, &unit)?;
+    write_privileged_file(TARGET_PATH, target)?;
+
+    run_sudo(&["systemctl", "daemon-reload"])?;
+    run_sudo(&["systemctl", "enable", "--now", "candyconnect-helper.service", "candyconnect.target"])?;
+    Ok(())
+}
+
+/// Write `contents` to a root-owned path via `sudo tee`, the same
+/// "write as the current user, install with a single elevated command"
+/// split `killswitch::platform_engage` already uses for its rule files.
+#[cfg(target_os = "linux")]
+fn write_privileged_file(path: &str, contents: &str) -> Result<(), String> {
+    use std::process::{Command, Stdio};
+    let mut child = Command::new("sudo")
+        .args(&["tee", path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to run sudo tee: {}", e))?;
+    child
+        .stdin
+        .take()
+        .expect("piped stdin")
+        .write_all(contents.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("Failed to write {}", path));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn run_sudo(args: &[&str]) -> Result<(), String> {
+    let output = std::process::Command::new("sudo").args(args).output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn platform_uninstall() -> Result<(), String> {
+    let _ = run_sudo(&["systemctl", "disable", "--now", "candyconnect-helper.service", "candyconnect.target"]);
+    let _ = run_sudo(&["rm", "-f", UNIT_PATH, TARGET_PATH]);
+    let _ = run_sudo(&["systemctl", "daemon-reload"]);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn platform_is_installed() -> bool {
+    std::path::Path::new(UNIT_PATH).exists()
+}
+
+#[cfg(target_os = "windows")]
+const SERVICE_NAME: &str = "CandyConnectHelper";
+
+#[cfg(target_os = "windows")]
+fn platform_install(exe: &std::path::Path) -> Result<(), String> {
+    use std::os::windows::process::CommandExt;
+    // Same reasoning as the Linux `Environment=` line above: `install()`
+    // runs as the interactive user, so this is the only point that can
+    // correctly capture who's installing the helper. Passed as a `binPath=`
+    // argument rather than a config file, since `sc create` already gives us
+    // an elevated one-shot write and a config file would need its own ACL
+    // bootstrapping problem solved first.
+    let owner = std::env::var("USERNAME").unwrap_or_default();
+    let bin_path = format!("\"{}\" {} --owner {}", exe.display(), HELPER_ARG, owner);
+    let output = std::process::Command::new("sc")
+        .args(&["create", SERVICE_NAME, "binPath=", &bin_path, "start=", "auto"])
+        .creation_flags(0x08000000)
+        .output()
+        .map_err(|e| format!("Failed to run sc create: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("sc create failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let _ = std::process::Command::new("sc")
+        .args(&["start", SERVICE_NAME])
+        .creation_flags(0x08000000)
+        .output();
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn platform_uninstall() -> Result<(), String> {
+    use std::os::windows::process::CommandExt;
+    let _ = std::process::Command::new("sc")
+        .args(&["stop", SERVICE_NAME])
+        .creation_flags(0x08000000)
+        .output();
+    let output = std::process::Command::new("sc")
+        .args(&["delete", SERVICE_NAME])
+        .creation_flags(0x08000000)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("sc delete failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn platform_is_installed() -> bool {
+    use std::os::windows::process::CommandExt;
+    std::process::Command::new("sc")
+        .args(&["query", SERVICE_NAME])
+        .creation_flags(0x08000000)
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn platform_install(_exe: &std::path::Path) -> Result<(), String> {
+    Err("Privileged helper installation is not supported on this platform yet".to_string())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn platform_uninstall() -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn platform_is_installed() -> bool {
+    false
+}
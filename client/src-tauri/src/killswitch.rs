@@ -0,0 +1,276 @@
+//! Opt-in kill switch: block all traffic that isn't going to the VPN
+//! endpoint or out through the tunnel interface, for as long as the user is
+//! connected.
+//!
+//! Every protocol driver in this crate only reacts to a dropped tunnel by
+//! emitting `vpn-disconnected` — the OS routing table still has a default
+//! route out the normal interface, so a crashed helper or a dropped SA
+//! leaks cleartext traffic until something reconnects. `engage` installs a
+//! deny-by-default firewall rule set scoped to the VPN server's IP and the
+//! tunnel interfaces this crate brings up (see `is_vpn_interface`), and
+//! `disengage` removes it. Deliberately asymmetric: nothing in this module
+//! is called from the monitor threads that detect a drop — leaving the
+//! rules in place until `disengage` runs (from `stop_vpn`, or at the start
+//! of the next `engage`) is what makes a dead tunnel fail closed rather than
+//! silently falling back to the normal interface.
+
+use std::process::Command;
+
+/// Table/anchor/rule-group name shared across platforms so `disengage` can
+/// find exactly what `engage` installed.
+const NAME: &str = "candyconnect_ks";
+
+/// Read the user's opt-in from `settings.json` the same way
+/// `generate_sing_box_config` reads its own settings — this module has no
+/// state of its own beyond what's on disk.
+pub fn is_enabled(app_data_dir: &std::path::Path) -> bool {
+    let settings_path = app_data_dir.join("settings.json");
+    std::fs::read_to_string(settings_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .and_then(|v| v["killSwitch"].as_bool())
+        .unwrap_or(false)
+}
+
+/// Resolve `server` (already-an-IP or a hostname) to the single address the
+/// firewall rules should carve an exception for.
+fn resolve_endpoint(server: &str) -> Result<String, String> {
+    use std::net::ToSocketAddrs;
+    (server, 0u16)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .map(|addr| addr.ip().to_string())
+        .ok_or_else(|| format!("Could not resolve {} to install kill switch rules", server))
+}
+
+/// Install the deny-by-default rule set, replacing any rule set left behind
+/// by a previous session (crash, forced shutdown) so re-engaging never
+/// stacks duplicate rules.
+pub fn engage(server: &str, app_data_dir: &std::path::Path) -> Result<(), String> {
+    let _ = disengage();
+    let endpoint_ip = resolve_endpoint(server)?;
+    platform_engage(&endpoint_ip, app_data_dir)
+}
+
+/// Tear down the rule set. Safe to call when nothing is installed (e.g.
+/// every `stop_vpn`, whether or not the kill switch was ever engaged) —
+/// mirrors the "delete first, ignore errors" tolerance the rest of this
+/// crate's cleanup code already has for `nmcli connection delete` etc.
+pub fn disengage() -> Result<(), String> {
+    platform_disengage()
+}
+
+#[cfg(target_os = "linux")]
+fn platform_engage(endpoint_ip: &str, app_data_dir: &std::path::Path) -> Result<(), String> {
+    let ruleset_dir = app_data_dir.join("killswitch");
+    std::fs::create_dir_all(&ruleset_dir).map_err(|e| e.to_string())?;
+    let ruleset_path = ruleset_dir.join("nftables.conf");
+
+    let ruleset = format!(
+        "table inet {name} {{\n  chain output {{\n    type filter hook output priority 0; policy drop;\n    oifname \"lo\" accept\n    ip daddr {ip} accept\n    ip6 daddr ::1 accept\n    ct state established,related accept\n    oifname {{ \"tun0\", \"tun1\", \"wg0\", \"cc-wg0\", \"ppp0\", \"utun0\" }} accept\n  }}\n}}\n",
+        name = NAME, ip = endpoint_ip
+    );
+    std::fs::write(&ruleset_path, &ruleset).map_err(|e| e.to_string())?;
+
+    let output = Command::new("sudo")
+        .args(&["nft", "-f"])
+        .arg(&ruleset_path)
+        .output()
+        .map_err(|e| format!("Failed to run nft: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("nft -f failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn platform_disengage() -> Result<(), String> {
+    let _ = Command::new("sudo").args(&["nft", "delete", "table", "inet", NAME]).output();
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn platform_engage(endpoint_ip: &str, app_data_dir: &std::path::Path) -> Result<(), String> {
+    let ruleset_dir = app_data_dir.join("killswitch");
+    std::fs::create_dir_all(&ruleset_dir).map_err(|e| e.to_string())?;
+    let ruleset_path = ruleset_dir.join("pf.conf");
+
+    let ruleset = format!(
+        "pass quick on lo0 all\npass out quick proto tcp to {ip} all\npass out quick proto udp to {ip} all\npass quick on tun0 all\npass quick on utun0 all\npass quick on wg0 all\nblock drop out all\n",
+        ip = endpoint_ip
+    );
+    std::fs::write(&ruleset_path, &ruleset).map_err(|e| e.to_string())?;
+
+    let _ = Command::new("sudo").args(&["pfctl", "-E"]).output();
+    let output = Command::new("sudo")
+        .args(&["pfctl", "-a", NAME, "-f"])
+        .arg(&ruleset_path)
+        .output()
+        .map_err(|e| format!("Failed to run pfctl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("pfctl -f failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn platform_disengage() -> Result<(), String> {
+    let _ = Command::new("sudo").args(&["pfctl", "-a", NAME, "-F", "all"]).output();
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+const RULE_NAMES: &[&str] = &[
+    "CandyConnect-KillSwitch-AllowVPN",
+    "CandyConnect-KillSwitch-AllowTunnel",
+    "CandyConnect-KillSwitch-BlockAll",
+];
+
+/// Adapter name/description fields `Get-NetAdapter` reports, just enough to
+/// tell a VPN-owned adapter apart from a physical one.
+#[cfg(target_os = "windows")]
+#[derive(serde::Deserialize)]
+struct AdapterInfo {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "InterfaceDescription")]
+    description: String,
+    #[serde(rename = "Status")]
+    status: String,
+}
+
+/// Aliases of every up, non-VPN adapter — what the block rule should be
+/// scoped to, so it never has a chance to match traffic actually leaving
+/// through the tunnel. Reuses the same marker list `read_net_counters`'s
+/// Windows branch already matches adapter descriptions against.
+#[cfg(target_os = "windows")]
+fn windows_non_vpn_adapter_aliases() -> Result<Vec<String>, String> {
+    use std::os::windows::process::CommandExt;
+    let output = Command::new("powershell")
+        .args(&["-NoProfile", "-Command", "Get-NetAdapter | Select-Object Name, InterfaceDescription, Status | ConvertTo-Json -Compress"])
+        .creation_flags(0x08000000)
+        .output()
+        .map_err(|e| format!("Failed to query network adapters: {}", e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let trimmed = stdout.trim();
+    if trimmed.is_empty() {
+        return Err("No network adapters found".to_string());
+    }
+    // `ConvertTo-Json` emits a bare object instead of a one-element array
+    // when there's only one adapter — normalize so serde always sees a list.
+    let normalized = if trimmed.starts_with('[') { trimmed.to_string() } else { format!("[{}]", trimmed) };
+    let adapters: Vec<AdapterInfo> = serde_json::from_str(&normalized).map_err(|e| format!("Failed to parse adapter list: {}", e))?;
+
+    let aliases: Vec<String> = adapters
+        .into_iter()
+        .filter(|a| a.status.eq_ignore_ascii_case("Up"))
+        .filter(|a| !crate::windows_adapter_name_matches_vpn(&a.name) && !crate::windows_adapter_name_matches_vpn(&a.description))
+        .map(|a| a.name)
+        .collect();
+
+    if aliases.is_empty() {
+        return Err("No non-VPN network adapters found to scope the kill switch block rule".to_string());
+    }
+    Ok(aliases)
+}
+
+/// Two ranges covering all of IPv4 except `ip` itself, for `-RemoteAddress`
+/// on the block rule — see `platform_engage` for why this matters.
+#[cfg(target_os = "windows")]
+fn ipv4_exclude_range(ip: &str) -> Option<String> {
+    let addr: std::net::Ipv4Addr = ip.parse().ok()?;
+    let n = u32::from(addr);
+    let mut ranges = Vec::new();
+    if n > 0 {
+        ranges.push(format!("0.0.0.0-{}", std::net::Ipv4Addr::from(n - 1)));
+    }
+    if n < u32::MAX {
+        ranges.push(format!("{}-255.255.255.255", std::net::Ipv4Addr::from(n + 1)));
+    }
+    Some(ranges.join(","))
+}
+
+#[cfg(target_os = "windows")]
+fn platform_engage(endpoint_ip: &str, _app_data_dir: &std::path::Path) -> Result<(), String> {
+    use std::os::windows::process::CommandExt;
+
+    let powershell = |script: &str| -> Result<(), String> {
+        let output = Command::new("powershell")
+            .args(&["-NoProfile", "-Command", script])
+            .creation_flags(0x08000000)
+            .output()
+            .map_err(|e| format!("Failed to run powershell: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("powershell failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(())
+    };
+
+    powershell(&format!(
+        "New-NetFirewallRule -DisplayName '{}' -Direction Outbound -Action Allow -RemoteAddress '{}' -Profile Any | Out-Null",
+        RULE_NAMES[0], endpoint_ip
+    ))?;
+    powershell(&format!(
+        "New-NetFirewallRule -DisplayName '{}' -Direction Outbound -Action Allow -RemoteAddress '127.0.0.1' -Profile Any | Out-Null",
+        RULE_NAMES[1]
+    ))?;
+
+    // Windows Advanced Firewall evaluates explicit block rules before
+    // explicit allow rules regardless of add order or specificity, so a
+    // blanket "block all outbound" alongside the allow rules above doesn't
+    // fail closed while the tunnel keeps working — it severs the VPN
+    // connection itself the instant it's engaged. There's no rule-weight
+    // knob exposed by `netsh`/`New-NetFirewallRule` to fix that by
+    // priority, so instead the block rule's own match criteria are scoped
+    // so it never overlaps what's allowed: `-InterfaceAlias` restricts it
+    // to adapters that aren't carrying VPN traffic (the same scoping the
+    // Linux/macOS branches get from `oifname`/`pass quick on`), and
+    // `-RemoteAddress` is two ranges that together mean "not the VPN
+    // endpoint" so the server's own handshake/keepalive traffic over the
+    // physical adapter is never a match for this rule either.
+    let non_vpn_aliases = windows_non_vpn_adapter_aliases()?;
+    let alias_list = non_vpn_aliases
+        .iter()
+        .map(|a| format!("'{}'", a.replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let block_cmd = match ipv4_exclude_range(endpoint_ip) {
+        Some(ranges) => format!(
+            "New-NetFirewallRule -DisplayName '{}' -Direction Outbound -Action Block -InterfaceAlias {} -RemoteAddress {} -Profile Any | Out-Null",
+            RULE_NAMES[2], alias_list, ranges
+        ),
+        // Non-IPv4 endpoint (e.g. IPv6): fall back to scoping by interface
+        // alias alone, which still keeps the tunnel interface itself open.
+        None => format!(
+            "New-NetFirewallRule -DisplayName '{}' -Direction Outbound -Action Block -InterfaceAlias {} -Profile Any | Out-Null",
+            RULE_NAMES[2], alias_list
+        ),
+    };
+    powershell(&block_cmd)?;
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn platform_disengage() -> Result<(), String> {
+    use std::os::windows::process::CommandExt;
+    for name in RULE_NAMES {
+        let _ = Command::new("powershell")
+            .args(&["-NoProfile", "-Command", &format!("Remove-NetFirewallRule -DisplayName '{}' -ErrorAction SilentlyContinue", name)])
+            .creation_flags(0x08000000)
+            .output();
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn platform_engage(_endpoint_ip: &str, _app_data_dir: &std::path::Path) -> Result<(), String> {
+    Err("Kill switch is not supported on this platform".to_string())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn platform_disengage() -> Result<(), String> {
+    Ok(())
+}
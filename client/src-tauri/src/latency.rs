@@ -0,0 +1,148 @@
+//! Firewall-tolerant latency measurement.
+//!
+//! ICMP ping — the old `measure_latency` implementation — is frequently
+//! dropped outright on the censored networks this app targets, and scraping
+//! `time=` out of locale-dependent `ping` stdout is fragile even when it
+//! isn't. `measure_tcp` times the plain TCP three-way handshake instead,
+//! which every reachable host answers regardless of ICMP policy.
+//! `measure_proxy_delay` goes a step further and measures what the
+//! connected tunnel actually delivers: a real HTTP round trip through the
+//! local SOCKS proxy, timed to first byte, so the number reported matches
+//! what the selected core's own traffic experiences rather than just proxy
+//! reachability.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_ATTEMPTS: u8 = 3;
+
+/// A 0-byte "connectivity check" endpoint, so the proxy-delay probe's timing
+/// is dominated by round-trip time rather than any response body transfer.
+/// Plain HTTP (not HTTPS) so this doesn't need a TLS dependency just to
+/// measure a round trip.
+const PROXY_PROBE_HOST: &str = "clients3.google.com";
+const PROXY_PROBE_PATH: &str = "/generate_204";
+
+#[derive(Clone, Serialize)]
+pub struct LatencyResult {
+    #[serde(rename = "avgMs")]
+    pub avg_ms: u64,
+    #[serde(rename = "minMs")]
+    pub min_ms: u64,
+    #[serde(rename = "maxMs")]
+    pub max_ms: u64,
+    #[serde(rename = "lossPct")]
+    pub loss_pct: u8,
+    pub attempts: u8,
+}
+
+/// Time the TCP handshake to `host:port`, `attempts` times, reporting the
+/// average/min/max RTT across the attempts that connected and the
+/// percentage that timed out or were refused.
+pub fn measure_tcp(host: &str, port: u16, attempts: u8) -> Result<LatencyResult, String> {
+    let socket_addr = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Could not resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("No address found for {}", host))?;
+
+    let mut samples = Vec::new();
+    for _ in 0..attempts {
+        let start = Instant::now();
+        if TcpStream::connect_timeout(&socket_addr, CONNECT_TIMEOUT).is_ok() {
+            samples.push(start.elapsed().as_millis() as u64);
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(format!("All {} connection attempts to {}:{} timed out or failed", attempts, host, port));
+    }
+
+    let loss_pct = (((attempts as usize - samples.len()) * 100) / attempts as usize) as u8;
+    Ok(LatencyResult {
+        avg_ms: samples.iter().sum::<u64>() / samples.len() as u64,
+        min_ms: *samples.iter().min().unwrap(),
+        max_ms: *samples.iter().max().unwrap(),
+        loss_pct,
+        attempts,
+    })
+}
+
+/// Measure end-to-end tunnel latency: open a SOCKS5 connection to the
+/// already-running local proxy, have it CONNECT to the probe endpoint, send
+/// a plain HTTP GET, and time to the first byte of the response.
+pub fn measure_proxy_delay(proxy_host: &str, proxy_port: u16) -> Result<u64, String> {
+    let proxy_addr = (proxy_host, proxy_port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Could not resolve proxy {}:{}: {}", proxy_host, proxy_port, e))?
+        .next()
+        .ok_or_else(|| "No address found for proxy".to_string())?;
+
+    let mut stream = TcpStream::connect_timeout(&proxy_addr, CONNECT_TIMEOUT)
+        .map_err(|e| format!("Failed to connect to local proxy {}:{}: {}", proxy_host, proxy_port, e))?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT)).ok();
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT)).ok();
+
+    socks5_connect(&mut stream, PROXY_PROBE_HOST, 80)?;
+
+    let start = Instant::now();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        PROXY_PROBE_PATH, PROXY_PROBE_HOST
+    );
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut first_byte = [0u8; 1];
+    stream.read_exact(&mut first_byte).map_err(|e| format!("No response from proxy: {}", e))?;
+
+    Ok(start.elapsed().as_millis() as u64)
+}
+
+/// Minimal SOCKS5 client handshake — no-auth negotiation followed by a
+/// CONNECT request, just enough to tunnel one outbound stream through the
+/// local proxy. Only implements what's actually used here, the same
+/// "enough of the protocol to do the one thing we need" approach `vici.rs`
+/// takes with VICI's own binary protocol. `pub(crate)` so `geoip`'s exit-IP
+/// probe can reuse it instead of re-implementing the same handshake.
+pub(crate) fn socks5_connect(stream: &mut TcpStream, dest_host: &str, dest_port: u16) -> Result<(), String> {
+    // Greeting: version 5, 1 auth method offered, "no auth".
+    stream.write_all(&[0x05, 0x01, 0x00]).map_err(|e| e.to_string())?;
+    let mut greeting_reply = [0u8; 2];
+    stream.read_exact(&mut greeting_reply).map_err(|e| e.to_string())?;
+    if greeting_reply != [0x05, 0x00] {
+        return Err("SOCKS5 proxy requires authentication this probe doesn't support".to_string());
+    }
+
+    // CONNECT request with a domain-name address type, so the proxy (not
+    // this process) resolves the probe endpoint.
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, dest_host.len() as u8];
+    request.extend_from_slice(dest_host.as_bytes());
+    request.extend_from_slice(&dest_port.to_be_bytes());
+    stream.write_all(&request).map_err(|e| e.to_string())?;
+
+    // Reply: VER REP RSV ATYP, then an address whose length depends on
+    // ATYP, then a 2-byte port — read the fixed header first.
+    let mut reply_header = [0u8; 4];
+    stream.read_exact(&mut reply_header).map_err(|e| e.to_string())?;
+    if reply_header[1] != 0x00 {
+        return Err(format!("SOCKS5 CONNECT failed with reply code {}", reply_header[1]));
+    }
+    let addr_len = match reply_header[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len_byte = [0u8; 1];
+            stream.read_exact(&mut len_byte).map_err(|e| e.to_string())?;
+            len_byte[0] as usize
+        }
+        other => return Err(format!("Unknown SOCKS5 address type {}", other)),
+    };
+    let mut discard = vec![0u8; addr_len + 2]; // bound address + port, unused
+    stream.read_exact(&mut discard).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
@@ -1,4 +1,18 @@
+mod conn_state;
+mod geoip;
+mod helper;
+mod killswitch;
+mod latency;
+mod log_events;
+mod net_stats;
+mod profiles;
 mod sing_box_helper;
+mod stats;
+mod supervisor;
+mod tracing_log;
+mod version_gate;
+#[cfg(target_os = "linux")]
+mod vici;
 
 use std::fs;
 use std::process::Stdio;
@@ -85,8 +99,9 @@ async fn generate_sing_box_config(app: tauri::AppHandle, server_address: String)
     serde_json::to_string_pretty(&config).map_err(|e| e.to_string())
 }
 
-/// Kill a process by PID. Used to tear down the companion process in TUN mode
-/// (e.g. kill sing-box when xray exits, or vice versa).
+/// Hard-kill a process by PID. `kill_process_gracefully` falls back to this
+/// once its grace window has passed; the DNSTT/SSH paths call it directly,
+/// with no graceful stop first.
 fn kill_process(pid: u32) {
     #[cfg(target_os = "windows")]
     {
@@ -104,98 +119,125 @@ fn kill_process(pid: u32) {
     }
 }
 
-#[tauri::command]
-async fn start_vpn(
-    app: tauri::AppHandle,
-    config_json: String,
-    mode: String,
-) -> Result<(), String> {
-    use std::process::{Command, Stdio};
-    use std::io::{BufRead, BufReader};
-    use std::thread;
+/// Default grace window given to a child before a graceful stop escalates to
+/// a hard kill. xray/sing-box need a moment to tear down their TUN interface
+/// and restore routing tables — jumping straight to SIGKILL/`taskkill /F` can
+/// leave the default route pointed at a dead tun device.
+const GRACEFUL_STOP_GRACE: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Attempt a graceful stop of `child`: send SIGTERM (Unix) or a close signal
+/// to its process group (Windows `taskkill` without `/F`), then poll
+/// `try_wait` until it exits or `grace` elapses. Only escalates to a hard
+/// kill if the child is still alive after the grace window. Requires the
+/// owning `Child` handle (not just its PID) so the grace loop can observe the
+/// real exit via `try_wait` instead of guessing.
+fn kill_process_gracefully(child: &mut std::process::Child, grace: std::time::Duration) {
+    let pid = child.id();
 
-    let app_data_dir = app.path().app_data_dir().expect("Failed to get app dir");
-    let resource_dir = app.path().resource_dir().unwrap_or_else(|_| std::env::current_dir().unwrap());
-    let logs_path = app_data_dir.join("candy.logs");
+    #[cfg(target_os = "windows")]
+    {
+        use std::process::Command;
+        use std::os::windows::process::CommandExt;
+        // No `/F` here — this asks the process (and its tree) to close so it
+        // can unwind the TUN interface before we escalate to a hard kill.
+        let _ = Command::new("taskkill")
+            .args(&["/PID", &pid.to_string(), "/T"])
+            .creation_flags(0x08000000)
+            .spawn();
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        unsafe {
+            libc::kill(pid as i32, libc::SIGTERM);
+        }
+    }
 
-    // 1. Validate and save Xray config
-    let xray_config_path = app_data_dir.join("xray_config.json");
+    let deadline = std::time::Instant::now() + grace;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) | Err(_) => return,
+            Ok(None) => {
+                if std::time::Instant::now() >= deadline {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+        }
+    }
 
-    // Validate that config_json is valid JSON before writing
-    let parsed: serde_json::Value = serde_json::from_str(&config_json).map_err(|e| {
-        let err_msg = format!("Invalid Xray config JSON: {}. First 200 chars: {}", e, config_json.chars().take(200).collect::<String>());
-        let _ = append_log(&logs_path, "error", &err_msg);
-        err_msg
-    })?;
+    // Still alive after the grace window — escalate to a hard kill.
+    kill_process(pid);
+    let _ = child.wait();
+}
 
-    // Re-serialize to ensure clean formatting
-    let clean_config = serde_json::to_string_pretty(&parsed).unwrap_or(config_json.clone());
-    fs::write(&xray_config_path, &clean_config).map_err(|e| e.to_string())?;
+/// Handle a watcher thread exposes so another thread can ask its companion
+/// process to gracefully stop (SIGTERM → grace window → SIGKILL) instead of
+/// being killed out from under it by bare PID.
+struct CompanionHandle {
+    pid: u32,
+    stop_tx: std::sync::mpsc::Sender<()>,
+}
 
-    // Log config snippet for debugging (first 200 chars)
-    let config_preview: String = clean_config.chars().take(200).collect();
-    let _ = append_log(&logs_path, "info", &format!("Xray config saved ({} bytes): {}...", clean_config.len(), config_preview));
+impl CompanionHandle {
+    /// Request a graceful stop of the companion process. No-op if the
+    /// watcher thread has already exited and dropped its receiver.
+    fn request_stop(&self) {
+        let _ = self.stop_tx.send(());
+    }
+}
 
-    // 2. Determine paths using a more robust search
-    let resolve_tool = |base: &std::path::Path, rel_path: &str| -> std::path::PathBuf {
-        let p1 = base.join(rel_path);
-        if p1.exists() { return p1; }
-        let p2 = base.join("resources").join(rel_path);
-        if p2.exists() { return p2; }
-        p1 // fallback to p1
-    };
+/// The `StopFlag` for the VPN session currently managed by `start_vpn`'s
+/// supervised watcher threads, if any. `stop_vpn` trips it so an unexpected
+/// exit caused by the kill commands it runs isn't mistaken for a crash and
+/// restarted out from under the user.
+fn active_stop_flag() -> &'static Mutex<Option<supervisor::StopFlag>> {
+    static FLAG: std::sync::OnceLock<Mutex<Option<supervisor::StopFlag>>> = std::sync::OnceLock::new();
+    FLAG.get_or_init(|| Mutex::new(None))
+}
 
-    let xray_bin = resolve_tool(&resource_dir, if cfg!(target_os = "windows") { "xray/xray.exe" } else { "xray/xray" });
-    let sing_box_bin = resolve_tool(&resource_dir, if cfg!(target_os = "windows") { "sing-box/sing-box.exe" } else { "sing-box/sing-box" });
+/// Spawn the Xray engine and its stdout/stderr log-forwarding threads. Used
+/// both for the initial start and for supervised restarts after a crash.
+fn spawn_xray_process(
+    xray_bin: &std::path::Path,
+    xray_config_path: &std::path::Path,
+    logs_path: &std::path::Path,
+    watcher: conn_state::StateWatcher,
+) -> Result<(std::process::Child, std::thread::JoinHandle<()>, std::thread::JoinHandle<()>), String> {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::thread;
 
-    // 3. Start Xray
-    let _ = append_log(&logs_path, "info", &format!("Starting Xray engine: {}", xray_bin.display()));
-    
-    let mut xray_cmd = Command::new(&xray_bin);
+    let mut xray_cmd = Command::new(xray_bin);
     xray_cmd
         .arg("-c")
-        .arg(&xray_config_path)
+        .arg(xray_config_path)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 
-    // Prevent console window flash on Windows
     #[cfg(target_os = "windows")]
     {
         use std::os::windows::process::CommandExt;
         xray_cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
     }
 
-    let mut xray_child = xray_cmd
-        .spawn()
-        .map_err(|e| {
-            let err_msg = format!("CRITICAL: Failed to spawn Xray: {}", e);
-            let _ = append_log(&logs_path, "error", &err_msg);
-            err_msg
-        })?;
-
-    let _ = append_log(&logs_path, "info", &format!("Xray process spawned successfully (PID: {})", xray_child.id()));
-
-    // Log whether the binary actually exists at the resolved path
-    if xray_bin.exists() {
-        let _ = append_log(&logs_path, "info", &format!("Xray binary confirmed at: {}", xray_bin.display()));
-    } else {
-        let _ = append_log(&logs_path, "error", &format!("Xray binary NOT FOUND at: {}", xray_bin.display()));
-    }
+    let mut xray_child = xray_cmd.spawn().map_err(|e| format!("CRITICAL: Failed to spawn Xray: {}", e))?;
+    let _ = append_log(logs_path, "info", &format!("Xray process spawned successfully (PID: {})", xray_child.id()));
 
-    // Log Xray output to candy.logs
     let stdout = xray_child.stdout.take().unwrap();
     let stderr = xray_child.stderr.take().unwrap();
 
-    let logs_path_clone = logs_path.clone();
-    let xray_stdout_thread = thread::spawn(move || {
+    let logs_out = logs_path.to_path_buf();
+    let watcher_out = watcher.clone();
+    let stdout_thread = thread::spawn(move || {
         let reader = BufReader::new(stdout);
         for line in reader.lines() {
             match line {
                 Ok(l) if !l.trim().is_empty() => {
-                    let _ = append_log(&logs_path_clone, "info", &format!("[Xray] {}", l));
+                    let _ = append_log(&logs_out, "info", &format!("[Xray] {}", l));
+                    watcher_out.feed_line(&l, &conn_state::ReadyPatterns::XRAY);
                 }
                 Err(e) => {
-                    let _ = append_log(&logs_path_clone, "warn", &format!("[Xray] stdout read error: {}", e));
+                    let _ = append_log(&logs_out, "warn", &format!("[Xray] stdout read error: {}", e));
                     break;
                 }
                 _ => {}
@@ -203,16 +245,18 @@ async fn start_vpn(
         }
     });
 
-    let logs_path_err = logs_path.clone();
-    let xray_stderr_thread = thread::spawn(move || {
+    let logs_err = logs_path.to_path_buf();
+    let watcher_err = watcher.clone();
+    let stderr_thread = thread::spawn(move || {
         let reader = BufReader::new(stderr);
         for line in reader.lines() {
             match line {
                 Ok(l) if !l.trim().is_empty() => {
-                    let _ = append_log(&logs_path_err, "error", &format!("[Xray] {}", l));
+                    let _ = append_log(&logs_err, "error", &format!("[Xray] {}", l));
+                    watcher_err.feed_line(&l, &conn_state::ReadyPatterns::XRAY);
                 }
                 Err(e) => {
-                    let _ = append_log(&logs_path_err, "warn", &format!("[Xray] stderr read error: {}", e));
+                    let _ = append_log(&logs_err, "warn", &format!("[Xray] stderr read error: {}", e));
                     break;
                 }
                 _ => {}
@@ -220,205 +264,624 @@ async fn start_vpn(
         }
     });
 
-    // Brief health check: wait a moment to see if xray survives startup
-    thread::sleep(std::time::Duration::from_millis(500));
-    match xray_child.try_wait() {
-        Ok(Some(status)) => {
-            // Process already exited — wait for output threads to capture everything
-            let _ = xray_stdout_thread.join();
-            let _ = xray_stderr_thread.join();
-            let err_msg = format!("Xray exited immediately with {}", status);
-            let _ = append_log(&logs_path, "error", &err_msg);
-            use tauri::Emitter;
-            let _ = app.emit("vpn-disconnected", ());
-            return Err(err_msg);
-        }
-        Ok(None) => {
-            let _ = append_log(&logs_path, "info", "Xray process is running after health check");
-        }
-        Err(e) => {
-            let _ = append_log(&logs_path, "warn", &format!("Could not check Xray status: {}", e));
-        }
+    Ok((xray_child, stdout_thread, stderr_thread))
+}
+
+/// Spawn the Sing-box TUN engine and its stdout/stderr log-forwarding
+/// threads. Used both for the initial start and for supervised restarts.
+fn spawn_sing_box_process(
+    sing_box_bin: &std::path::Path,
+    sb_config_path: &std::path::Path,
+    logs_path: &std::path::Path,
+    watcher: conn_state::StateWatcher,
+) -> Result<(std::process::Child, std::thread::JoinHandle<()>, std::thread::JoinHandle<()>), String> {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    let mut sb_cmd = Command::new(sing_box_bin);
+    sb_cmd
+        .arg("run")
+        .arg("-c")
+        .arg(sb_config_path)
+        .env("ENABLE_DEPRECATED_SPECIAL_OUTBOUNDS", "true")
+        .env("ENABLE_DEPRECATED_TUN_ADDRESS_X", "true")
+        .env("ENABLE_DEPRECATED_WIREGUARD_OUTBOUND", "true")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        sb_cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
     }
 
-    // Shared PID holders for cross-process cleanup in TUN mode
-    let xray_pid = xray_child.id();
-    let sing_box_pid: Arc<Mutex<Option<u32>>> = Arc::new(Mutex::new(None));
-    let is_tun_mode = mode == "tun";
+    let mut sb_child = sb_cmd.spawn().map_err(|e| format!("CRITICAL: Failed to spawn Sing-box: {}", e))?;
+    let _ = append_log(logs_path, "info", &format!("Sing-box TUN engine spawned successfully (PID: {})", sb_child.id()));
 
-    // Watch Xray exit in background — wait for output threads to flush before emitting event
-    let app_h_xray = app.clone();
-    let logs_p_xray_exit = logs_path.clone();
-    let sing_box_pid_for_xray = Arc::clone(&sing_box_pid);
-    thread::spawn(move || {
-        let exit_status = xray_child.wait();
-        // Wait for stdout/stderr reader threads to finish processing all output
-        let _ = xray_stdout_thread.join();
-        let _ = xray_stderr_thread.join();
-        match exit_status {
-            Ok(status) => {
-                let _ = append_log(&logs_p_xray_exit, "warn", &format!("Xray process exited with {}", status));
-            }
-            Err(e) => {
-                let _ = append_log(&logs_p_xray_exit, "error", &format!("Failed to wait on Xray process: {}", e));
-            }
-        }
-        // In TUN mode, kill sing-box if it's still running
-        if is_tun_mode {
-            if let Some(sb_pid) = *sing_box_pid_for_xray.lock().unwrap() {
-                let _ = append_log(&logs_p_xray_exit, "info", &format!("Xray exited — killing companion Sing-box (PID {})", sb_pid));
-                kill_process(sb_pid);
+    let sb_stdout = sb_child.stdout.take().unwrap();
+    let sb_stderr = sb_child.stderr.take().unwrap();
+
+    let logs_out = logs_path.to_path_buf();
+    let watcher_out = watcher.clone();
+    let stdout_thread = thread::spawn(move || {
+        let reader = BufReader::new(sb_stdout);
+        for line in reader.lines() {
+            match line {
+                Ok(l) if !l.trim().is_empty() => {
+                    let _ = append_log(&logs_out, "info", &format!("[Sing-box] {}", l));
+                    watcher_out.feed_line(&l, &conn_state::ReadyPatterns::SING_BOX);
+                }
+                Err(e) => {
+                    let _ = append_log(&logs_out, "warn", &format!("[Sing-box] stdout read error: {}", e));
+                    break;
+                }
+                _ => {}
             }
         }
-        use tauri::Emitter;
-        let _ = app_h_xray.emit("vpn-disconnected", ());
     });
 
-    // 4. If TUN mode, also start Sing-box
-    if mode == "tun" {
-        let _ = append_log(&logs_path, "info", "Initializing TUN mode orchestration...");
-        let mut server_address = "127.0.0.1".to_string();
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&config_json) {
-            if let Some(outbound) = json["outbounds"].as_array().and_then(|a| a.get(0)) {
-               if let Some(vnext) = outbound["settings"]["vnext"].as_array().and_then(|a| a.get(0)) {
-                   if let Some(addr) = vnext["address"].as_str() {
-                       server_address = addr.to_string();
-                   }
-               }
+    let logs_err = logs_path.to_path_buf();
+    let watcher_err = watcher.clone();
+    let stderr_thread = thread::spawn(move || {
+        let reader = BufReader::new(sb_stderr);
+        for line in reader.lines() {
+            match line {
+                Ok(l) if !l.trim().is_empty() => {
+                    let _ = append_log(&logs_err, "error", &format!("[Sing-box] {}", l));
+                    watcher_err.feed_line(&l, &conn_state::ReadyPatterns::SING_BOX);
+                }
+                Err(e) => {
+                    let _ = append_log(&logs_err, "warn", &format!("[Sing-box] stderr read error: {}", e));
+                    break;
+                }
+                _ => {}
             }
         }
+    });
 
-        let sb_config = generate_sing_box_config(app.clone(), server_address).await?;
-        let sb_config_path = app_data_dir.join("sing_box_config.json");
-        fs::write(&sb_config_path, &sb_config).map_err(|e| e.to_string())?;
-
-        let _ = append_log(&logs_path, "info", &format!("Starting Sing-box routing engine: {}", sing_box_bin.display()));
-
-        let mut sb_cmd = Command::new(&sing_box_bin);
-        sb_cmd
-            .arg("run")
-            .arg("-c")
-            .arg(&sb_config_path)
-            .env("ENABLE_DEPRECATED_SPECIAL_OUTBOUNDS", "true")
-            .env("ENABLE_DEPRECATED_TUN_ADDRESS_X", "true")
-.env("ENABLE_DEPRECATED_WIREGUARD_OUTBOUND", "true")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+    Ok((sb_child, stdout_thread, stderr_thread))
+}
 
-        // Prevent console window flash on Windows
-        #[cfg(target_os = "windows")]
-        {
-            use std::os::windows::process::CommandExt;
-            sb_cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+const READINESS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+const READINESS_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+const READINESS_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Poll `probe` every `READINESS_POLL_INTERVAL` until it returns `true`,
+/// bailing out immediately if `child` has already exited and timing out
+/// after `READINESS_TIMEOUT`. Replaces a blind `thread::sleep` + single
+/// `try_wait` with a deterministic signal: a process can pass `try_wait`
+/// well before it's actually listening on its port.
+fn wait_for_ready(
+    child: &mut std::process::Child,
+    mut probe: impl FnMut() -> bool,
+) -> Result<(), String> {
+    let deadline = std::time::Instant::now() + READINESS_TIMEOUT;
+    loop {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("process exited before becoming ready: {}", status));
         }
+        if probe() {
+            return Ok(());
+        }
+        if std::time::Instant::now() >= deadline {
+            return Err(format!("timed out after {:?} waiting for readiness", READINESS_TIMEOUT));
+        }
+        std::thread::sleep(READINESS_POLL_INTERVAL);
+    }
+}
 
-        let mut sb_child = match sb_cmd.spawn() {
-            Ok(child) => child,
-            Err(e) => {
-                let err_msg = format!("CRITICAL: Failed to spawn Sing-box: {}", e);
-                let _ = append_log(&logs_path, "error", &err_msg);
-                // Kill xray since TUN mode can't work without sing-box
-                let _ = append_log(&logs_path, "info", &format!("Killing Xray (PID {}) because Sing-box failed to start", xray_pid));
-                kill_process(xray_pid);
-                use tauri::Emitter;
-                let _ = app.emit("vpn-disconnected", ());
-                return Err(err_msg);
-            }
-        };
+/// Probe mode for [`wait_for_ready`]: attempt a TCP connect to `host:port`.
+fn probe_tcp(host: &str, port: u16) -> bool {
+    use std::net::{TcpStream, ToSocketAddrs};
+    match (host, port).to_socket_addrs() {
+        Ok(addrs) => addrs
+            .into_iter()
+            .any(|addr| TcpStream::connect_timeout(&addr, READINESS_CONNECT_TIMEOUT).is_ok()),
+        Err(_) => false,
+    }
+}
 
-        let _ = append_log(&logs_path, "info", &format!("Sing-box TUN engine spawned successfully (PID: {})", sb_child.id()));
+/// Same as [`probe_tcp`] but for a pre-formatted `host:port` string.
+fn probe_tcp_addr(addr: &str) -> bool {
+    match addr.rsplit_once(':') {
+        Some((host, port)) => port.parse().map(|p| probe_tcp(host, p)).unwrap_or(false),
+        None => false,
+    }
+}
 
-        if sing_box_bin.exists() {
-            let _ = append_log(&logs_path, "info", &format!("Sing-box binary confirmed at: {}", sing_box_bin.display()));
-        } else {
-            let _ = append_log(&logs_path, "error", &format!("Sing-box binary NOT FOUND at: {}", sing_box_bin.display()));
-        }
+/// Probe mode for [`wait_for_ready`] in TUN mode: check that a VPN-owned
+/// network interface has actually come up, since the SOCKS/TCP probe above
+/// doesn't apply once traffic is routed at the interface level.
+#[cfg(target_os = "linux")]
+fn tun_interface_ready() -> bool {
+    std::fs::read_to_string("/proc/net/dev")
+        .map(|content| {
+            content
+                .lines()
+                .skip(2)
+                .filter_map(|l| l.split_once(':'))
+                .any(|(name, _)| is_vpn_interface(name.trim()))
+        })
+        .unwrap_or(false)
+}
 
-        let sb_stdout = sb_child.stdout.take().unwrap();
-        let sb_stderr = sb_child.stderr.take().unwrap();
+#[cfg(target_os = "macos")]
+fn tun_interface_ready() -> bool {
+    std::process::Command::new("ifconfig")
+        .arg("-l")
+        .output()
+        .ok()
+        .map(|o| String::from_utf8_lossy(&o.stdout).split_whitespace().any(is_vpn_interface))
+        .unwrap_or(false)
+}
 
-        let logs_path_sb = logs_path.clone();
-        let sb_stdout_thread = thread::spawn(move || {
-            let reader = BufReader::new(sb_stdout);
-            for line in reader.lines() {
-                match line {
-                    Ok(l) if !l.trim().is_empty() => {
-                        let _ = append_log(&logs_path_sb, "info", &format!("[Sing-box] {}", l));
-                    }
-                    Err(e) => {
-                        let _ = append_log(&logs_path_sb, "warn", &format!("[Sing-box] stdout read error: {}", e));
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-        });
+#[cfg(target_os = "windows")]
+fn tun_interface_ready() -> bool {
+    use std::os::windows::process::CommandExt;
+    std::process::Command::new("powershell")
+        .args(&[
+            "-NoProfile", "-NonInteractive", "-Command",
+            "(Get-NetAdapter | Where-Object { $_.Name -match 'tun|wg|vpn|tap|candyconnect|sing' }).Count",
+        ])
+        .creation_flags(0x08000000)
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse::<u32>().ok())
+        .map(|count| count > 0)
+        .unwrap_or(false)
+}
 
-        let logs_path_sb_err = logs_path.clone();
-        let sb_stderr_thread = thread::spawn(move || {
-            let reader = BufReader::new(sb_stderr);
-            for line in reader.lines() {
-                match line {
-                    Ok(l) if !l.trim().is_empty() => {
-                        let _ = append_log(&logs_path_sb_err, "error", &format!("[Sing-box] {}", l));
-                    }
-                    Err(e) => {
-                        let _ = append_log(&logs_path_sb_err, "warn", &format!("[Sing-box] stderr read error: {}", e));
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-        });
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn tun_interface_ready() -> bool {
+    true
+}
 
-        // Brief health check for sing-box
-        thread::sleep(std::time::Duration::from_millis(500));
-        match sb_child.try_wait() {
-            Ok(Some(status)) => {
-                let _ = sb_stdout_thread.join();
-                let _ = sb_stderr_thread.join();
-                let err_msg = format!("Sing-box exited immediately with {}", status);
-                let _ = append_log(&logs_path, "error", &err_msg);
-                // Kill xray since TUN mode can't work without sing-box
-                let _ = append_log(&logs_path, "info", &format!("Killing Xray (PID {}) because Sing-box failed to start", xray_pid));
-                kill_process(xray_pid);
-                use tauri::Emitter;
-                let _ = app.emit("vpn-disconnected", ());
-                return Err(err_msg);
-            }
-            Ok(None) => {
-                let _ = append_log(&logs_path, "info", "Sing-box process is running after health check");
-            }
-            Err(e) => {
-                let _ = append_log(&logs_path, "warn", &format!("Could not check Sing-box status: {}", e));
-            }
+/// Pull the remote server address out of the first proxy outbound in an
+/// Xray config, trying the couple of shapes the supported protocols
+/// (VMess/VLESS, Shadowsocks/SOCKS, Trojan) put it in — used to scope the
+/// kill switch's allow exception when `start_vpn` only has the raw config
+/// JSON and no separate `server` argument of its own.
+fn extract_primary_server_address(config: &serde_json::Value) -> Option<String> {
+    config["outbounds"].as_array()?.iter().find_map(|outbound| {
+        if outbound["tag"].as_str() == Some("api") {
+            return None;
         }
+        outbound["settings"]["vnext"][0]["address"]
+            .as_str()
+            .or_else(|| outbound["settings"]["servers"][0]["address"].as_str())
+            .map(|s| s.to_string())
+    })
+}
 
-        // Store sing-box PID so the xray watcher can kill it if xray exits first
-        *sing_box_pid.lock().unwrap() = Some(sb_child.id());
+/// Post-connect hooks: engage the kill switch if the user has opted in via
+/// `settings.json` (logging either way), and drop the cached exit-country
+/// lookup so the next `resolve_exit_country` call re-resolves rather than
+/// reporting the previous session's exit node. Called once a connection has
+/// actually come up, from every protocol driver's success path.
+fn engage_kill_switch_if_enabled(server: &str, app_data_dir: &std::path::Path, logs_path: &std::path::Path) {
+    geoip::reset();
 
-        // Watch Sing-box exit in background — kill xray if sing-box exits first
-        let app_h_sb = app.clone();
-        let logs_p_sb_exit = logs_path.clone();
-        let xray_pid_for_sb = xray_pid;
-        thread::spawn(move || {
-            let exit_status = sb_child.wait();
-            let _ = sb_stdout_thread.join();
-            let _ = sb_stderr_thread.join();
-            match exit_status {
-                Ok(status) => {
-                    let _ = append_log(&logs_p_sb_exit, "warn", &format!("Sing-box process exited with {}", status));
-                }
-                Err(e) => {
-                    let _ = append_log(&logs_p_sb_exit, "error", &format!("Failed to wait on Sing-box process: {}", e));
-                }
-            }
-            // Kill xray since sing-box (TUN routing) is dead
-            let _ = append_log(&logs_p_sb_exit, "info", &format!("Sing-box exited — killing companion Xray (PID {})", xray_pid_for_sb));
-            kill_process(xray_pid_for_sb);
-            use tauri::Emitter;
-            let _ = app_h_sb.emit("vpn-disconnected", ());
-        });
+    if !killswitch::is_enabled(app_data_dir) {
+        return;
+    }
+    // Prefer the privileged helper when it's installed — no `sudo`/UAC
+    // prompt on every connect — falling back to engaging directly.
+    let result = if helper::is_installed() {
+        helper::engage_kill_switch(server, app_data_dir)
+    } else {
+        killswitch::engage(server, app_data_dir)
+    };
+    match result {
+        Ok(()) => {
+            let _ = append_log(logs_path, "info", "Kill switch engaged");
+        }
+        Err(e) => {
+            let _ = append_log(logs_path, "error", &format!("Kill switch failed to engage: {}", e));
+        }
+    }
+}
+
+#[tauri::command]
+async fn start_vpn(
+    app: tauri::AppHandle,
+    config_json: String,
+    mode: String,
+) -> Result<(), String> {
+    use std::process::{Command, Stdio};
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+
+    let app_data_dir = app.path().app_data_dir().expect("Failed to get app dir");
+    let resource_dir = app.path().resource_dir().unwrap_or_else(|_| std::env::current_dir().unwrap());
+    let logs_path = app_data_dir.join("candy.logs");
+
+    // 1. Determine paths using a more robust search
+    let resolve_tool = |base: &std::path::Path, rel_path: &str| -> std::path::PathBuf {
+        let p1 = base.join(rel_path);
+        if p1.exists() { return p1; }
+        let p2 = base.join("resources").join(rel_path);
+        if p2.exists() { return p2; }
+        p1 // fallback to p1
+    };
+
+    let xray_bin = resolve_tool(&resource_dir, if cfg!(target_os = "windows") { "xray/xray.exe" } else { "xray/xray" });
+    let sing_box_bin = resolve_tool(&resource_dir, if cfg!(target_os = "windows") { "sing-box/sing-box.exe" } else { "sing-box/sing-box" });
+
+    // 2. Pre-flight: gate on binary versions before writing any config or
+    // spawning anything, so a version mismatch surfaces as an actionable
+    // error instead of the child exiting immediately with a cryptic status.
+    let xray_version = version_gate::check_compatibility(version_gate::Binary::Xray, &xray_bin).map_err(|e| {
+        let _ = append_log(&logs_path, "error", &e);
+        e
+    })?;
+    let _ = append_log(&logs_path, "info", &format!("Xray version: {}", xray_version));
+    if mode == "tun" {
+        let sing_box_version = version_gate::check_compatibility(version_gate::Binary::SingBox, &sing_box_bin).map_err(|e| {
+            let _ = append_log(&logs_path, "error", &e);
+            e
+        })?;
+        let _ = append_log(&logs_path, "info", &format!("Sing-box version: {}", sing_box_version));
+    }
+
+    // 3. Validate and save Xray config
+    let xray_config_path = app_data_dir.join("xray_config.json");
+
+    // Validate that config_json is valid JSON before writing
+    let mut parsed: serde_json::Value = serde_json::from_str(&config_json).map_err(|e| {
+        let err_msg = format!("Invalid Xray config JSON: {}. First 200 chars: {}", e, config_json.chars().take(200).collect::<String>());
+        let _ = append_log(&logs_path, "error", &err_msg);
+        err_msg
+    })?;
+
+    // Turn on Xray's StatsService so the traffic poller below has something
+    // to query — otherwise the engine is a black box with only log lines.
+    stats::inject_xray_stats_service(&mut parsed, stats::XRAY_STATS_API_ADDR);
+
+    // Re-serialize to ensure clean formatting
+    let clean_config = serde_json::to_string_pretty(&parsed).unwrap_or(config_json.clone());
+    fs::write(&xray_config_path, &clean_config).map_err(|e| e.to_string())?;
+
+    // Log config snippet for debugging (first 200 chars)
+    let config_preview: String = clean_config.chars().take(200).collect();
+    let _ = append_log(&logs_path, "info", &format!("Xray config saved ({} bytes): {}...", clean_config.len(), config_preview));
+
+    // 4. Start Xray
+    let _ = append_log(&logs_path, "info", &format!("Starting Xray engine: {}", xray_bin.display()));
+
+    // Log whether the binary actually exists at the resolved path
+    if xray_bin.exists() {
+        let _ = append_log(&logs_path, "info", &format!("Xray binary confirmed at: {}", xray_bin.display()));
+    } else {
+        let _ = append_log(&logs_path, "error", &format!("Xray binary NOT FOUND at: {}", xray_bin.display()));
+    }
+
+    let xray_state = conn_state::StateWatcher::new(app.clone(), "xray");
+    let (mut xray_child, mut xray_stdout_thread, mut xray_stderr_thread) =
+        spawn_xray_process(&xray_bin, &xray_config_path, &logs_path, xray_state.clone())?;
+
+    // Readiness: resolve as soon as Xray's own log reports it started (or a
+    // fatal line appears), falling back to an active SOCKS-inbound probe if
+    // neither shows up — a process can pass `try_wait` well before it's
+    // actually listening.
+    let settings_path = app_data_dir.join("settings.json");
+    let (proxy_host, proxy_port) = fs::read_to_string(&settings_path)
+        .ok()
+        .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+        .map(|s| (
+            s["proxyHost"].as_str().unwrap_or("127.0.0.1").to_string(),
+            s["proxyPort"].as_u64().unwrap_or(10808) as u16,
+        ))
+        .unwrap_or_else(|| ("127.0.0.1".to_string(), 10808));
+
+    match conn_state::await_ready(&mut xray_child, &xray_state, READINESS_TIMEOUT, || probe_tcp(&proxy_host, proxy_port)) {
+        Ok(()) => {
+            let _ = append_log(&logs_path, "info", &format!("Xray SOCKS inbound ready at {}:{}", proxy_host, proxy_port));
+        }
+        Err(e) => {
+            let _ = xray_stdout_thread.join();
+            let _ = xray_stderr_thread.join();
+            let err_msg = format!("Xray did not become ready: {}", e);
+            let _ = append_log(&logs_path, "error", &err_msg);
+            use tauri::Emitter;
+            let _ = app.emit("vpn-disconnected", ());
+            return Err(err_msg);
+        }
+    }
+
+    // Shared companion handles for graceful cross-process teardown in TUN mode
+    let xray_pid = xray_child.id();
+    let sing_box_handle: Arc<Mutex<Option<CompanionHandle>>> = Arc::new(Mutex::new(None));
+    let (xray_stop_tx, mut xray_stop_rx) = std::sync::mpsc::channel::<()>();
+    let is_tun_mode = mode == "tun";
+
+    // Supervision: a deliberate `stop_vpn` trips this flag so the watcher
+    // below treats the exit it's about to see as intentional instead of a
+    // crash to restart from.
+    let stop_flag = supervisor::StopFlag::new();
+    *active_stop_flag().lock().unwrap() = Some(stop_flag.clone());
+
+    // Watch Xray exit in background — wait for output threads to flush before emitting event.
+    // Polls instead of blocking on `wait()` so it can also observe a graceful-stop request
+    // from the Sing-box watcher (via `xray_stop_rx`) and run the SIGTERM→grace→SIGKILL
+    // sequence itself, since it's the thread that owns `xray_child`. On an unexpected exit
+    // it respawns Xray with exponential backoff instead of dropping the whole VPN.
+    let app_h_xray = app.clone();
+    let logs_p_xray_exit = logs_path.clone();
+    let sing_box_handle_for_xray = Arc::clone(&sing_box_handle);
+    let xray_bin_for_watcher = xray_bin.clone();
+    let xray_config_path_for_watcher = xray_config_path.clone();
+    let stop_flag_for_xray = stop_flag.clone();
+    let xray_state_for_exit = xray_state.clone();
+    thread::spawn(move || {
+        let stop_flag = stop_flag_for_xray;
+        let crash_tracker = supervisor::CrashTracker::new();
+        crash_tracker.mark_spawned();
+
+        loop {
+            let exit_status = loop {
+                match xray_child.try_wait() {
+                    Ok(Some(status)) => break Ok(status),
+                    Ok(None) => {}
+                    Err(e) => break Err(e),
+                }
+                if xray_stop_rx.try_recv().is_ok() {
+                    let _ = append_log(&logs_p_xray_exit, "info", "Gracefully stopping Xray (SIGTERM, grace window)...");
+                    kill_process_gracefully(&mut xray_child, GRACEFUL_STOP_GRACE);
+                    break xray_child.wait();
+                }
+                thread::sleep(std::time::Duration::from_millis(200));
+            };
+            // Wait for stdout/stderr reader threads to finish processing all output
+            let _ = xray_stdout_thread.join();
+            let _ = xray_stderr_thread.join();
+            match &exit_status {
+                Ok(status) => {
+                    let _ = append_log(&logs_p_xray_exit, "warn", &format!("Xray process exited with {}", status));
+                }
+                Err(e) => {
+                    let _ = append_log(&logs_p_xray_exit, "error", &format!("Failed to wait on Xray process: {}", e));
+                }
+            }
+
+            if stop_flag.is_stopped() {
+                // In TUN mode, gracefully stop sing-box too — this was a deliberate disconnect.
+                if is_tun_mode {
+                    if let Some(handle) = sing_box_handle_for_xray.lock().unwrap().as_ref() {
+                        let _ = append_log(&logs_p_xray_exit, "info", &format!("Xray stopped — gracefully stopping companion Sing-box (PID {})", handle.pid));
+                        handle.request_stop();
+                    }
+                }
+                xray_state_for_exit.mark_disconnected();
+                use tauri::Emitter;
+                let _ = app_h_xray.emit("vpn-disconnected", ());
+                break;
+            }
+
+            // Unexpected exit — this is what the supervisor exists for.
+            let (attempt, maxed_out) = crash_tracker.record_crash();
+            if maxed_out {
+                let _ = append_log(&logs_p_xray_exit, "error", "Xray crashed too many times in a row — giving up");
+                stop_flag.request_stop();
+                if is_tun_mode {
+                    if let Some(handle) = sing_box_handle_for_xray.lock().unwrap().as_ref() {
+                        handle.request_stop();
+                    }
+                }
+                xray_state_for_exit.mark_disconnected();
+                use tauri::Emitter;
+                let _ = app_h_xray.emit("vpn-failed", ());
+                break;
+            }
+
+            let delay = supervisor::backoff_delay(attempt);
+            let _ = append_log(&logs_p_xray_exit, "warn", &format!("Xray exited unexpectedly — restarting in {:?} (attempt {})", delay, attempt + 1));
+            use tauri::Emitter;
+            let _ = app_h_xray.emit("vpn-reconnecting", ());
+            thread::sleep(delay);
+
+            xray_state_for_exit.reset();
+            match spawn_xray_process(&xray_bin_for_watcher, &xray_config_path_for_watcher, &logs_p_xray_exit, xray_state_for_exit.clone()) {
+                Ok((new_child, new_stdout, new_stderr)) => {
+                    xray_child = new_child;
+                    xray_stdout_thread = new_stdout;
+                    xray_stderr_thread = new_stderr;
+                    crash_tracker.mark_spawned();
+                    let _ = app_h_xray.emit("vpn-restored", ());
+                }
+                Err(e) => {
+                    let _ = append_log(&logs_p_xray_exit, "error", &format!("Failed to restart Xray: {}", e));
+                    stop_flag.request_stop();
+                    let _ = app_h_xray.emit("vpn-failed", ());
+                    break;
+                }
+            }
+        }
+    });
+
+    // 4. If TUN mode, also start Sing-box
+    if mode == "tun" {
+        let _ = append_log(&logs_path, "info", "Initializing TUN mode orchestration...");
+        let mut server_address = "127.0.0.1".to_string();
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&config_json) {
+            if let Some(outbound) = json["outbounds"].as_array().and_then(|a| a.get(0)) {
+               if let Some(vnext) = outbound["settings"]["vnext"].as_array().and_then(|a| a.get(0)) {
+                   if let Some(addr) = vnext["address"].as_str() {
+                       server_address = addr.to_string();
+                   }
+               }
+            }
+        }
+
+        let sb_config = generate_sing_box_config(app.clone(), server_address).await?;
+
+        // Turn on sing-box's Clash API so the traffic poller has a
+        // management endpoint to query — otherwise TUN mode has no
+        // telemetry at all.
+        let mut sb_config_json: serde_json::Value = serde_json::from_str(&sb_config).map_err(|e| e.to_string())?;
+        stats::inject_clash_api(&mut sb_config_json, stats::CLASH_API_ADDR);
+        let sb_config = serde_json::to_string_pretty(&sb_config_json).unwrap_or(sb_config);
+
+        let sb_config_path = app_data_dir.join("sing_box_config.json");
+        fs::write(&sb_config_path, &sb_config).map_err(|e| e.to_string())?;
+
+        let _ = append_log(&logs_path, "info", &format!("Starting Sing-box routing engine: {}", sing_box_bin.display()));
+
+        if sing_box_bin.exists() {
+            let _ = append_log(&logs_path, "info", &format!("Sing-box binary confirmed at: {}", sing_box_bin.display()));
+        } else {
+            let _ = append_log(&logs_path, "error", &format!("Sing-box binary NOT FOUND at: {}", sing_box_bin.display()));
+        }
+
+        let sb_state = conn_state::StateWatcher::new(app.clone(), "sing-box");
+        let (mut sb_child, mut sb_stdout_thread, mut sb_stderr_thread) =
+            match spawn_sing_box_process(&sing_box_bin, &sb_config_path, &logs_path, sb_state.clone()) {
+                Ok(spawned) => spawned,
+                Err(err_msg) => {
+                    let _ = append_log(&logs_path, "error", &err_msg);
+                    // Gracefully stop Xray since TUN mode can't work without
+                    // sing-box — tell the supervisor this is deliberate first,
+                    // same as stop_vpn, so its watcher doesn't mistake the
+                    // exit for a crash and respawn Xray.
+                    let _ = append_log(&logs_path, "info", &format!("Stopping Xray (PID {}) because Sing-box failed to start", xray_pid));
+                    stop_flag.request_stop();
+                    let _ = xray_stop_tx.send(());
+                    use tauri::Emitter;
+                    let _ = app.emit("vpn-disconnected", ());
+                    return Err(err_msg);
+                }
+            };
+
+        // Readiness: resolve as soon as Sing-box's own log reports it
+        // started (or a fatal line appears), falling back to an active
+        // TUN-interface probe instead of a blind sleep.
+        match conn_state::await_ready(&mut sb_child, &sb_state, READINESS_TIMEOUT, || tun_interface_ready()) {
+            Ok(()) => {
+                let _ = append_log(&logs_path, "info", "Sing-box TUN interface ready");
+            }
+            Err(e) => {
+                let _ = sb_stdout_thread.join();
+                let _ = sb_stderr_thread.join();
+                let err_msg = format!("Sing-box did not become ready: {}", e);
+                let _ = append_log(&logs_path, "error", &err_msg);
+                // Gracefully stop Xray since TUN mode can't work without
+                // sing-box — tell the supervisor this is deliberate first,
+                // same as stop_vpn, so its watcher doesn't mistake the exit
+                // for a crash and respawn Xray.
+                let _ = append_log(&logs_path, "info", &format!("Stopping Xray (PID {}) because Sing-box failed to start", xray_pid));
+                stop_flag.request_stop();
+                let _ = xray_stop_tx.send(());
+                use tauri::Emitter;
+                let _ = app.emit("vpn-disconnected", ());
+                return Err(err_msg);
+            }
+        }
+
+        // Store a stoppable handle so the xray watcher can request a graceful
+        // stop of sing-box if xray exits first. Rebuilt on every respawn below.
+        let (sb_stop_tx, mut sb_stop_rx) = std::sync::mpsc::channel::<()>();
+        *sing_box_handle.lock().unwrap() = Some(CompanionHandle { pid: sb_child.id(), stop_tx: sb_stop_tx });
+
+        // Watch Sing-box exit in background — gracefully stop xray if sing-box gives up.
+        // Polls instead of blocking on `wait()` so it can also observe a graceful-stop
+        // request from the Xray watcher (via `sb_stop_rx`). On an unexpected exit it
+        // respawns Sing-box with exponential backoff instead of tearing the tunnel down.
+        let app_h_sb = app.clone();
+        let logs_p_sb_exit = logs_path.clone();
+        let xray_stop_tx_for_sb = xray_stop_tx.clone();
+        let sing_box_handle_for_sb = Arc::clone(&sing_box_handle);
+        let sing_box_bin_for_watcher = sing_box_bin.clone();
+        let sb_config_path_for_watcher = sb_config_path.clone();
+        let stop_flag_for_sb = stop_flag.clone();
+        let sb_state_for_exit = sb_state.clone();
+        thread::spawn(move || {
+            let crash_tracker = supervisor::CrashTracker::new();
+            crash_tracker.mark_spawned();
+
+            loop {
+                let exit_status = loop {
+                    match sb_child.try_wait() {
+                        Ok(Some(status)) => break Ok(status),
+                        Ok(None) => {}
+                        Err(e) => break Err(e),
+                    }
+                    if sb_stop_rx.try_recv().is_ok() {
+                        let _ = append_log(&logs_p_sb_exit, "info", "Gracefully stopping Sing-box (SIGTERM, grace window)...");
+                        kill_process_gracefully(&mut sb_child, GRACEFUL_STOP_GRACE);
+                        break sb_child.wait();
+                    }
+                    thread::sleep(std::time::Duration::from_millis(200));
+                };
+                let _ = sb_stdout_thread.join();
+                let _ = sb_stderr_thread.join();
+                match &exit_status {
+                    Ok(status) => {
+                        let _ = append_log(&logs_p_sb_exit, "warn", &format!("Sing-box process exited with {}", status));
+                    }
+                    Err(e) => {
+                        let _ = append_log(&logs_p_sb_exit, "error", &format!("Failed to wait on Sing-box process: {}", e));
+                    }
+                }
+
+                if stop_flag_for_sb.is_stopped() {
+                    // Deliberate disconnect — gracefully stop xray too.
+                    let _ = append_log(&logs_p_sb_exit, "info", "Sing-box stopped — gracefully stopping companion Xray");
+                    let _ = xray_stop_tx_for_sb.send(());
+                    sb_state_for_exit.mark_disconnected();
+                    use tauri::Emitter;
+                    let _ = app_h_sb.emit("vpn-disconnected", ());
+                    break;
+                }
+
+                let (attempt, maxed_out) = crash_tracker.record_crash();
+                if maxed_out {
+                    let _ = append_log(&logs_p_sb_exit, "error", "Sing-box crashed too many times in a row — giving up");
+                    stop_flag_for_sb.request_stop();
+                    let _ = xray_stop_tx_for_sb.send(());
+                    sb_state_for_exit.mark_disconnected();
+                    use tauri::Emitter;
+                    let _ = app_h_sb.emit("vpn-failed", ());
+                    break;
+                }
+
+                let delay = supervisor::backoff_delay(attempt);
+                let _ = append_log(&logs_p_sb_exit, "warn", &format!("Sing-box exited unexpectedly — restarting in {:?} (attempt {})", delay, attempt + 1));
+                use tauri::Emitter;
+                let _ = app_h_sb.emit("vpn-reconnecting", ());
+                thread::sleep(delay);
+
+                sb_state_for_exit.reset();
+                match spawn_sing_box_process(&sing_box_bin_for_watcher, &sb_config_path_for_watcher, &logs_p_sb_exit, sb_state_for_exit.clone()) {
+                    Ok((new_child, new_stdout, new_stderr)) => {
+                        sb_child = new_child;
+                        sb_stdout_thread = new_stdout;
+                        sb_stderr_thread = new_stderr;
+                        let (new_stop_tx, new_stop_rx) = std::sync::mpsc::channel::<()>();
+                        sb_stop_rx = new_stop_rx;
+                        *sing_box_handle_for_sb.lock().unwrap() = Some(CompanionHandle { pid: sb_child.id(), stop_tx: new_stop_tx });
+                        crash_tracker.mark_spawned();
+                        let _ = app_h_sb.emit("vpn-restored", ());
+                    }
+                    Err(e) => {
+                        let _ = append_log(&logs_p_sb_exit, "error", &format!("Failed to restart Sing-box: {}", e));
+                        stop_flag_for_sb.request_stop();
+                        let _ = xray_stop_tx_for_sb.send(());
+                        let _ = app_h_sb.emit("vpn-failed", ());
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    // Traffic telemetry: polls the management endpoints enabled above and
+    // emits `vpn-stats`. Tied to `stop_flag` so it stops as soon as either
+    // watcher thread above treats the exit as a deliberate disconnect.
+    let clash_addr = if is_tun_mode { Some(stats::CLASH_API_ADDR.to_string()) } else { None };
+    stats::spawn_poller(app.clone(), Some(xray_bin.clone()), clash_addr, stats::XRAY_STATS_API_ADDR.to_string(), stop_flag);
+
+    if let Some(server) = extract_primary_server_address(&parsed) {
+        engage_kill_switch_if_enabled(&server, &app_data_dir, &logs_path);
     }
 
     Ok(())
@@ -438,8 +901,6 @@ async fn start_wireguard(
     local_addresses: Vec<String>,
     mode: String,
 ) -> Result<(), String> {
-    use std::process::{Command, Stdio};
-    use std::io::{BufRead, BufReader};
     use std::thread;
     use crate::sing_box_helper::Config;
 
@@ -464,6 +925,15 @@ async fn start_wireguard(
         if cfg!(target_os = "windows") { "sing-box/sing-box.exe" } else { "sing-box/sing-box" }
     );
 
+    // Pre-flight: gate on the sing-box version before writing any config or
+    // spawning anything, so an incompatible build fails with an actionable
+    // error instead of the child exiting immediately with a cryptic status.
+    let sing_box_version = version_gate::check_compatibility(version_gate::Binary::SingBox, &sing_box_bin).map_err(|e| {
+        let _ = append_log(&logs_path, "error", &e);
+        e
+    })?;
+    let _ = append_log(&logs_path, "info", &format!("Sing-box version: {}", sing_box_version));
+
     // Build the correct sing-box config depending on mode
     let sb_config = if mode == "tun" {
         // TUN mode: TUN inbound + WireGuard outbound with full key material
@@ -540,6 +1010,12 @@ async fn start_wireguard(
         serde_json::to_string_pretty(&cfg).map_err(|e| e.to_string())?
     };
 
+    // Turn on sing-box's Clash API so the traffic poller has a management
+    // endpoint to query — otherwise WireGuard mode has no telemetry at all.
+    let mut sb_config_json: serde_json::Value = serde_json::from_str(&sb_config).map_err(|e| e.to_string())?;
+    stats::inject_clash_api(&mut sb_config_json, stats::CLASH_API_ADDR);
+    let sb_config = serde_json::to_string_pretty(&sb_config_json).unwrap_or(sb_config);
+
     // Write sing-box config
     let sb_config_path = app_data_dir.join("sing_box_config.json");
     fs::write(&sb_config_path, &sb_config).map_err(|e| e.to_string())?;
@@ -548,96 +1024,135 @@ async fn start_wireguard(
     ));
 
     // Spawn sing-box
-    let mut sb_cmd = Command::new(&sing_box_bin);
-    sb_cmd
-        .arg("run")
-        .arg("-c")
-        .arg(&sb_config_path)
-        .env("ENABLE_DEPRECATED_SPECIAL_OUTBOUNDS", "true")
-        .env("ENABLE_DEPRECATED_TUN_ADDRESS_X", "true")
-        .env("ENABLE_DEPRECATED_WIREGUARD_OUTBOUND", "true")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-
-    #[cfg(target_os = "windows")]
-    {
-        use std::os::windows::process::CommandExt;
-        sb_cmd.creation_flags(0x08000000);
-    }
-
-    let mut sb_child = sb_cmd.spawn().map_err(|e| {
-        let msg = format!("CRITICAL: Failed to spawn sing-box for WireGuard: {}", e);
-        let _ = append_log(&logs_path, "error", &msg);
-        msg
-    })?;
-
-    let _ = append_log(&logs_path, "info", &format!(
-        "WireGuard sing-box spawned (PID: {})", sb_child.id()
-    ));
-
-    let sb_stdout = sb_child.stdout.take().unwrap();
-    let sb_stderr = sb_child.stderr.take().unwrap();
-
-    let logs1 = logs_path.clone();
-    thread::spawn(move || {
-        let reader = BufReader::new(sb_stdout);
-        for line in reader.lines() {
-            match line {
-                Ok(l) if !l.trim().is_empty() => {
-                    let _ = append_log(&logs1, "info", &format!("[WG/sing-box] {}", l));
-                }
-                Err(_) => break,
-                _ => {}
-            }
-        }
-    });
-
-    let logs2 = logs_path.clone();
-    thread::spawn(move || {
-        let reader = BufReader::new(sb_stderr);
-        for line in reader.lines() {
-            match line {
-                Ok(l) if !l.trim().is_empty() => {
-                    let _ = append_log(&logs2, "error", &format!("[WG/sing-box] {}", l));
-                }
-                Err(_) => break,
-                _ => {}
-            }
+    let sb_state = conn_state::StateWatcher::new(app.clone(), "sing-box");
+    let (mut sb_child, mut sb_stdout_thread, mut sb_stderr_thread) =
+        spawn_sing_box_process(&sing_box_bin, &sb_config_path, &logs_path, sb_state.clone())?;
+
+    // Readiness: resolve as soon as sing-box's own log reports it started
+    // (or a fatal line appears), falling back to an active probe instead of
+    // a blind sleep — TUN mode waits for the tunnel interface, proxy mode
+    // waits for the SOCKS inbound.
+    let readiness = if mode == "tun" {
+        conn_state::await_ready(&mut sb_child, &sb_state, READINESS_TIMEOUT, || tun_interface_ready())
+    } else {
+        let settings_path = app_data_dir.join("settings.json");
+        let (proxy_host, proxy_port) = fs::read_to_string(&settings_path)
+            .ok()
+            .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+            .map(|s| (
+                s["proxyHost"].as_str().unwrap_or("127.0.0.1").to_string(),
+                s["proxyPort"].as_u64().unwrap_or(1080) as u16,
+            ))
+            .unwrap_or_else(|| ("127.0.0.1".to_string(), 1080));
+        conn_state::await_ready(&mut sb_child, &sb_state, READINESS_TIMEOUT, || probe_tcp(&proxy_host, proxy_port))
+    };
+    match readiness {
+        Ok(()) => {
+            let _ = append_log(&logs_path, "info", "WireGuard sing-box is ready");
         }
-    });
-
-    // Health check
-    thread::sleep(std::time::Duration::from_millis(700));
-    match sb_child.try_wait() {
-        Ok(Some(status)) => {
-            let err_msg = format!("WireGuard sing-box exited immediately with {}", status);
+        Err(e) => {
+            let _ = sb_stdout_thread.join();
+            let _ = sb_stderr_thread.join();
+            let err_msg = format!("WireGuard sing-box did not become ready: {}", e);
             let _ = append_log(&logs_path, "error", &err_msg);
             use tauri::Emitter;
             let _ = app.emit("vpn-disconnected", ());
             return Err(err_msg);
         }
-        Ok(None) => {
-            let _ = append_log(&logs_path, "info", "WireGuard sing-box is running");
-        }
-        Err(e) => {
-            let _ = append_log(&logs_path, "warn", &format!("Could not check WireGuard sing-box status: {}", e));
-        }
     }
 
-    // Watch process in background
+    // Supervision: a deliberate `stop_vpn` trips this flag so the watcher
+    // below treats the exit it's about to see as intentional instead of a
+    // crash to restart from.
+    let stop_flag = supervisor::StopFlag::new();
+    *active_stop_flag().lock().unwrap() = Some(stop_flag.clone());
+
+    // Watch process in background — respawns on an unexpected exit with
+    // exponential backoff instead of dropping the tunnel, same as `start_vpn`.
+    // There's no companion process to coordinate a graceful stop with here, so
+    // unlike the Xray/Sing-box pair this just polls for exit; `stop_vpn`'s
+    // hard kill is what ends a deliberate disconnect.
     let app_h = app.clone();
     let logs_exit = logs_path.clone();
+    let sing_box_bin_for_watcher = sing_box_bin.clone();
+    let sb_config_path_for_watcher = sb_config_path.clone();
+    let sb_state_for_exit = sb_state.clone();
     thread::spawn(move || {
-        let _ = sb_child.wait();
-        let _ = append_log(&logs_exit, "warn", "WireGuard sing-box process exited");
-        use tauri::Emitter;
-        let _ = app_h.emit("vpn-disconnected", ());
-    });
-
-    Ok(())
-}
-
-/// Start OpenVPN as a client using a .ovpn config string.
+        let crash_tracker = supervisor::CrashTracker::new();
+        crash_tracker.mark_spawned();
+
+        loop {
+            let exit_status = loop {
+                match sb_child.try_wait() {
+                    Ok(Some(status)) => break Ok(status),
+                    Ok(None) => {}
+                    Err(e) => break Err(e),
+                }
+                thread::sleep(std::time::Duration::from_millis(200));
+            };
+            let _ = sb_stdout_thread.join();
+            let _ = sb_stderr_thread.join();
+            match &exit_status {
+                Ok(status) => {
+                    let _ = append_log(&logs_exit, "warn", &format!("WireGuard sing-box process exited with {}", status));
+                }
+                Err(e) => {
+                    let _ = append_log(&logs_exit, "error", &format!("Failed to wait on WireGuard sing-box process: {}", e));
+                }
+            }
+
+            if stop_flag.is_stopped() {
+                sb_state_for_exit.mark_disconnected();
+                use tauri::Emitter;
+                let _ = app_h.emit("vpn-disconnected", ());
+                break;
+            }
+
+            let (attempt, maxed_out) = crash_tracker.record_crash();
+            if maxed_out {
+                let _ = append_log(&logs_exit, "error", "WireGuard sing-box crashed too many times in a row — giving up");
+                stop_flag.request_stop();
+                sb_state_for_exit.mark_disconnected();
+                use tauri::Emitter;
+                let _ = app_h.emit("vpn-failed", ());
+                break;
+            }
+
+            let delay = supervisor::backoff_delay(attempt);
+            let _ = append_log(&logs_exit, "warn", &format!("WireGuard sing-box exited unexpectedly — restarting in {:?} (attempt {})", delay, attempt + 1));
+            use tauri::Emitter;
+            let _ = app_h.emit("vpn-reconnecting", ());
+            thread::sleep(delay);
+
+            sb_state_for_exit.reset();
+            match spawn_sing_box_process(&sing_box_bin_for_watcher, &sb_config_path_for_watcher, &logs_exit, sb_state_for_exit.clone()) {
+                Ok((new_child, new_stdout, new_stderr)) => {
+                    sb_child = new_child;
+                    sb_stdout_thread = new_stdout;
+                    sb_stderr_thread = new_stderr;
+                    crash_tracker.mark_spawned();
+                    let _ = app_h.emit("vpn-restored", ());
+                }
+                Err(e) => {
+                    let _ = append_log(&logs_exit, "error", &format!("Failed to restart WireGuard sing-box: {}", e));
+                    stop_flag.request_stop();
+                    let _ = app_h.emit("vpn-failed", ());
+                    break;
+                }
+            }
+        }
+    });
+
+    // Traffic telemetry: sing-box's Clash API is the only management
+    // endpoint here (there's no Xray process in WireGuard mode).
+    stats::spawn_poller(app.clone(), None, Some(stats::CLASH_API_ADDR.to_string()), stats::XRAY_STATS_API_ADDR.to_string(), stop_flag);
+
+    engage_kill_switch_if_enabled(&server, &app_data_dir, &logs_path);
+
+    Ok(())
+}
+
+/// Start OpenVPN as a client using a .ovpn config string.
 /// Writes the config to a temp file and spawns openvpn process.
 #[tauri::command]
 async fn start_openvpn(
@@ -789,22 +1304,22 @@ async fn start_openvpn(
         }
     });
 
-    // Health check — openvpn takes a moment to establish connection
-    thread::sleep(std::time::Duration::from_millis(1500));
-    match ovpn_child.try_wait() {
-        Ok(Some(status)) => {
-            let err_msg = format!("OpenVPN exited immediately with {} — check logs for details", status);
+    // Readiness: actively probe for the TUN interface OpenVPN brings up
+    // instead of a blind sleep — it can pass `try_wait` well before routing
+    // is actually in place.
+    match wait_for_ready(&mut ovpn_child, || tun_interface_ready()) {
+        Ok(()) => {
+            let _ = append_log(&logs_path, "info", "OpenVPN TUN interface ready");
+            use tauri::Emitter;
+            let _ = app.emit("vpn-connected", ());
+        }
+        Err(e) => {
+            let err_msg = format!("OpenVPN did not become ready: {} — check logs for details", e);
             let _ = append_log(&logs_path, "error", &err_msg);
             use tauri::Emitter;
             let _ = app.emit("vpn-disconnected", ());
             return Err(err_msg);
         }
-        Ok(None) => {
-            let _ = append_log(&logs_path, "info", "OpenVPN process is running after health check");
-        }
-        Err(e) => {
-            let _ = append_log(&logs_path, "warn", &format!("Could not check OpenVPN status: {}", e));
-        }
     }
 
     // Watch OpenVPN exit in background
@@ -820,9 +1335,39 @@ async fn start_openvpn(
         let _ = app_h.emit("vpn-disconnected", ());
     });
 
+    if let Some(server) = profiles::import_ovpn(&ovpn_config).ok().map(|p| p.server) {
+        engage_kill_switch_if_enabled(&server, &app_data_dir, &logs_path);
+    }
+
     Ok(())
 }
 
+/// Best-effort application of a DNS server pushed by an OpenVPN server's
+/// `PUSH_REPLY` (seen as a `dhcp-option DNS <addr>` directive on the
+/// management channel). There's no interface name to hand in from the
+/// management protocol, so this targets the same well-known
+/// adapter/service name each platform's native VPN driver already assumes.
+fn apply_pushed_dns(addr: &str, logs_path: &std::path::Path) {
+    let _ = append_log(logs_path, "info", &format!("OpenVPN pushed DNS: {}", addr));
+
+    #[cfg(target_os = "linux")]
+    {
+        let _ = std::process::Command::new("resolvectl").args(&["dns", "tun0", addr]).output();
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let _ = std::process::Command::new("networksetup").args(&["-setdnsservers", "Wi-Fi", addr]).output();
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        let _ = std::process::Command::new("netsh")
+            .args(&["interface", "ip", "set", "dns", "name=CandyConnect-OpenVPN", "static", addr])
+            .creation_flags(0x08000000)
+            .output();
+    }
+}
+
 /// Resolve the DNSTT resolver setting string into command-line arguments for dnstt-client.
 /// Returns (flag, address) e.g. ("-udp", "8.8.8.8:53") or ("-doh", "https://dns.google/dns-query").
 fn resolve_dnstt_resolver(resolver: &str) -> (&'static str, &'static str) {
@@ -845,6 +1390,20 @@ fn resolve_dnstt_resolver(resolver: &str) -> (&'static str, &'static str) {
     }
 }
 
+/// Strip a `resolve_dnstt_resolver` address down to just the host, for the
+/// kill switch's allow exception — DNSTT's actual egress traffic goes to
+/// the resolver, not to `server_ip` (the SSH endpoint it tunnels to), so
+/// that's what needs the firewall carve-out. Handles both `host:port`
+/// (UDP/DoT) and `https://host/path` (DoH) shapes.
+fn dnstt_resolver_host(resolver_addr: &str) -> String {
+    let without_scheme = resolver_addr.trim_start_matches("https://").trim_start_matches("http://");
+    let without_path = without_scheme.split('/').next().unwrap_or(without_scheme);
+    match without_path.rsplit_once(':') {
+        Some((host, _port)) => host.to_string(),
+        None => without_path.to_string(),
+    }
+}
+
 #[tauri::command]
 async fn start_dnstt(
     app: tauri::AppHandle,
@@ -925,13 +1484,17 @@ async fn start_dnstt(
     let dnstt_stdout = dnstt_child.stdout.take().unwrap();
     let dnstt_stderr = dnstt_child.stderr.take().unwrap();
 
+    let dnstt_state = conn_state::StateWatcher::new(app.clone(), "dnstt");
+
     let logs_p1 = logs_path.clone();
+    let dnstt_state_out = dnstt_state.clone();
     let dnstt_stdout_thread = thread::spawn(move || {
         let reader = BufReader::new(dnstt_stdout);
         for line in reader.lines() {
             match line {
                 Ok(l) if !l.trim().is_empty() => {
                     let _ = append_log(&logs_p1, "info", &format!("[DNSTT] {}", l));
+                    dnstt_state_out.feed_line(&l, &conn_state::ReadyPatterns::DNSTT);
                 }
                 Err(e) => {
                     let _ = append_log(&logs_p1, "warn", &format!("[DNSTT] stdout read error: {}", e));
@@ -943,12 +1506,14 @@ async fn start_dnstt(
     });
 
     let logs_p2 = logs_path.clone();
+    let dnstt_state_err = dnstt_state.clone();
     let dnstt_stderr_thread = thread::spawn(move || {
         let reader = BufReader::new(dnstt_stderr);
         for line in reader.lines() {
             match line {
                 Ok(l) if !l.trim().is_empty() => {
                     let _ = append_log(&logs_p2, "error", &format!("[DNSTT] {}", l));
+                    dnstt_state_err.feed_line(&l, &conn_state::ReadyPatterns::DNSTT);
                 }
                 Err(e) => {
                     let _ = append_log(&logs_p2, "warn", &format!("[DNSTT] stderr read error: {}", e));
@@ -959,24 +1524,27 @@ async fn start_dnstt(
         }
     });
 
-    // Health check: wait briefly to see if dnstt-client survives
-    thread::sleep(std::time::Duration::from_millis(800));
-    match dnstt_child.try_wait() {
-        Ok(Some(status)) => {
+    // Readiness: resolve as soon as dnstt-client's own log reports it's
+    // listening (or a fatal line appears), falling back to an active probe
+    // of the TCP tunnel instead of a blind sleep — it can be alive but not
+    // yet accepting, or die right after the window a fixed sleep would have
+    // checked.
+    let dnstt_listen_addr_for_probe = dnstt_listen_addr.clone();
+    match conn_state::await_ready(&mut dnstt_child, &dnstt_state, READINESS_TIMEOUT, || probe_tcp_addr(&dnstt_listen_addr_for_probe)) {
+        Ok(()) => {
+            let _ = append_log(&logs_path, "info", &format!("dnstt-client tunnel ready at {}", dnstt_listen_addr));
+            use tauri::Emitter;
+            let _ = app.emit("vpn-connected", ());
+        }
+        Err(e) => {
             let _ = dnstt_stdout_thread.join();
             let _ = dnstt_stderr_thread.join();
-            let err_msg = format!("dnstt-client exited immediately with {}", status);
+            let err_msg = format!("dnstt-client did not become ready: {}", e);
             let _ = append_log(&logs_path, "error", &err_msg);
             use tauri::Emitter;
             let _ = app.emit("vpn-disconnected", ());
             return Err(err_msg);
         }
-        Ok(None) => {
-            let _ = append_log(&logs_path, "info", "dnstt-client is running after health check");
-        }
-        Err(e) => {
-            let _ = append_log(&logs_path, "warn", &format!("Could not check dnstt-client status: {}", e));
-        }
     }
 
     let dnstt_pid = dnstt_child.id();
@@ -1109,25 +1677,24 @@ async fn start_dnstt(
         }
     });
 
-    // SSH health check
-    thread::sleep(std::time::Duration::from_millis(1500));
-    match ssh_child.try_wait() {
-        Ok(Some(status)) => {
+    // Readiness: actively probe the SSH SOCKS listener instead of a blind
+    // sleep.
+    match wait_for_ready(&mut ssh_child, || probe_tcp_addr(&ssh_socks_addr)) {
+        Ok(()) => {
+            let _ = append_log(&logs_path, "info", &format!("SSH SOCKS tunnel ready at {}", ssh_socks_addr));
+            use tauri::Emitter;
+            let _ = app.emit("vpn-connected", ());
+        }
+        Err(e) => {
             let _ = ssh_stdout_thread.join();
             let _ = ssh_stderr_thread.join();
-            let err_msg = format!("SSH tunnel exited immediately with {}", status);
+            let err_msg = format!("SSH tunnel did not become ready: {}", e);
             let _ = append_log(&logs_path, "error", &err_msg);
             kill_process(dnstt_pid);
             use tauri::Emitter;
             let _ = app.emit("vpn-disconnected", ());
             return Err(err_msg);
         }
-        Ok(None) => {
-            let _ = append_log(&logs_path, "info", "SSH tunnel is running after health check");
-        }
-        Err(e) => {
-            let _ = append_log(&logs_path, "warn", &format!("Could not check SSH tunnel status: {}", e));
-        }
     }
 
     let _ssh_pid = ssh_child.id();
@@ -1220,13 +1787,17 @@ async fn start_dnstt(
         let sb_stdout = sb_child.stdout.take().unwrap();
         let sb_stderr = sb_child.stderr.take().unwrap();
 
+        let sb_dnstt_state = conn_state::StateWatcher::new(app.clone(), "sing-box");
+
         let logs_sb1 = logs_path.clone();
+        let sb_dnstt_state_out = sb_dnstt_state.clone();
         let sb_stdout_thread = thread::spawn(move || {
             let reader = BufReader::new(sb_stdout);
             for line in reader.lines() {
                 match line {
                     Ok(l) if !l.trim().is_empty() => {
                         let _ = append_log(&logs_sb1, "info", &format!("[Sing-box/DNSTT] {}", l));
+                        sb_dnstt_state_out.feed_line(&l, &conn_state::ReadyPatterns::SING_BOX);
                     }
                     Err(e) => {
                         let _ = append_log(&logs_sb1, "warn", &format!("[Sing-box/DNSTT] stdout error: {}", e));
@@ -1238,12 +1809,14 @@ async fn start_dnstt(
         });
 
         let logs_sb2 = logs_path.clone();
+        let sb_dnstt_state_err = sb_dnstt_state.clone();
         let sb_stderr_thread = thread::spawn(move || {
             let reader = BufReader::new(sb_stderr);
             for line in reader.lines() {
                 match line {
                     Ok(l) if !l.trim().is_empty() => {
                         let _ = append_log(&logs_sb2, "error", &format!("[Sing-box/DNSTT] {}", l));
+                        sb_dnstt_state_err.feed_line(&l, &conn_state::ReadyPatterns::SING_BOX);
                     }
                     Err(e) => {
                         let _ = append_log(&logs_sb2, "warn", &format!("[Sing-box/DNSTT] stderr error: {}", e));
@@ -1254,13 +1827,19 @@ async fn start_dnstt(
             }
         });
 
-        // Health check for sing-box
-        thread::sleep(std::time::Duration::from_millis(500));
-        match sb_child.try_wait() {
-            Ok(Some(status)) => {
+        // Readiness: resolve as soon as sing-box's own log reports it
+        // started (or a fatal line appears), falling back to an active
+        // TUN-interface probe instead of a blind sleep.
+        match conn_state::await_ready(&mut sb_child, &sb_dnstt_state, READINESS_TIMEOUT, || tun_interface_ready()) {
+            Ok(()) => {
+                let _ = append_log(&logs_path, "info", "Sing-box (DNSTT TUN) interface ready");
+                use tauri::Emitter;
+                let _ = app.emit("vpn-connected", ());
+            }
+            Err(e) => {
                 let _ = sb_stdout_thread.join();
                 let _ = sb_stderr_thread.join();
-                let err_msg = format!("Sing-box (DNSTT TUN) exited immediately with {}", status);
+                let err_msg = format!("Sing-box (DNSTT TUN) did not become ready: {}", e);
                 let _ = append_log(&logs_path, "error", &err_msg);
                 let _ = append_log(&logs_path, "info", &format!("Killing dnstt-client (PID {}) because Sing-box failed", dnstt_pid));
                 kill_process(dnstt_pid);
@@ -1268,12 +1847,6 @@ async fn start_dnstt(
                 let _ = app.emit("vpn-disconnected", ());
                 return Err(err_msg);
             }
-            Ok(None) => {
-                let _ = append_log(&logs_path, "info", "Sing-box (DNSTT TUN) is running after health check");
-            }
-            Err(e) => {
-                let _ = append_log(&logs_path, "warn", &format!("Could not check Sing-box status: {}", e));
-            }
         }
 
         *sing_box_pid.lock().unwrap() = Some(sb_child.id());
@@ -1282,6 +1855,7 @@ async fn start_dnstt(
         let app_h_sb = app.clone();
         let logs_p_sb = logs_path.clone();
         let dnstt_pid_for_sb = dnstt_pid;
+        let sb_dnstt_state_for_exit = sb_dnstt_state.clone();
         thread::spawn(move || {
             let exit_status = sb_child.wait();
             let _ = sb_stdout_thread.join();
@@ -1296,15 +1870,125 @@ async fn start_dnstt(
             }
             let _ = append_log(&logs_p_sb, "info", &format!("Sing-box exited — killing dnstt-client (PID {})", dnstt_pid_for_sb));
             kill_process(dnstt_pid_for_sb);
+            sb_dnstt_state_for_exit.mark_disconnected();
             use tauri::Emitter;
             let _ = app_h_sb.emit("vpn-disconnected", ());
         });
     }
 
     let _ = append_log(&logs_path, "info", &format!("DNSTT connection established in {} mode", mode));
+
+    engage_kill_switch_if_enabled(&dnstt_resolver_host(resolver_addr), &app_data_dir, &logs_path);
+
     Ok(())
 }
 
+/// A single IKE/ESP crypto proposal, expressed once and translated into
+/// each platform's own configuration surface (PowerShell's
+/// `Set-VpnConnectionIPsecConfiguration` flags, nmcli/strongSwan `ike=`/
+/// `esp=` strings, and the macOS IKEv2 plist's security-association keys).
+struct IkeProposal {
+    /// strongSwan-style name, e.g. "aes128-sha256-modp3072" — also what gets
+    /// logged so a working proposal can be pinned by the caller.
+    name: &'static str,
+    ike: &'static str,
+    esp: &'static str,
+    windows_auth_transform: &'static str,
+    windows_cipher_transform: &'static str,
+    windows_dh_group: &'static str,
+    windows_encryption_method: &'static str,
+    windows_integrity_method: &'static str,
+    macos_encryption: &'static str,
+    macos_integrity: &'static str,
+    macos_dh_group: u32,
+}
+
+/// Built-in fallback ladder, most-modern first, ending in the
+/// `3des-sha1-modp1024` proposal Windows RRAS servers are stuck accepting.
+const IKE_PROPOSALS: &[IkeProposal] = &[
+    IkeProposal {
+        name: "aes128-sha256-modp3072",
+        ike: "aes128-sha256-modp3072",
+        esp: "aes128-sha256",
+        windows_auth_transform: "SHA256128",
+        windows_cipher_transform: "AES128",
+        // Windows' -DHGroup set has no MODP-3072; Group24 (2048-bit MODP) is sent instead.
+        windows_dh_group: "Group24",
+        windows_encryption_method: "AES128",
+        windows_integrity_method: "SHA256",
+        macos_encryption: "AES128",
+        macos_integrity: "SHA256",
+        macos_dh_group: 15,
+    },
+    IkeProposal {
+        name: "aes128-sha1-modp2048",
+        ike: "aes128-sha1-modp2048",
+        esp: "aes128-sha1",
+        windows_auth_transform: "SHA196",
+        windows_cipher_transform: "AES128",
+        windows_dh_group: "Group14",
+        windows_encryption_method: "AES128",
+        windows_integrity_method: "SHA1",
+        macos_encryption: "AES128",
+        macos_integrity: "SHA1-96",
+        macos_dh_group: 14,
+    },
+    IkeProposal {
+        name: "3des-sha1-modp1536",
+        ike: "3des-sha1-modp1536",
+        esp: "3des-sha1",
+        windows_auth_transform: "SHA196",
+        windows_cipher_transform: "DES3",
+        // Windows' -DHGroup set has no MODP-1536; Group2 (MODP-1024) is sent instead.
+        windows_dh_group: "Group2",
+        windows_encryption_method: "DES3",
+        windows_integrity_method: "SHA1",
+        macos_encryption: "3DES",
+        macos_integrity: "SHA1-96",
+        macos_dh_group: 5,
+    },
+    IkeProposal {
+        name: "3des-sha1-modp1024",
+        ike: "3des-sha1-modp1024",
+        esp: "3des-sha1",
+        windows_auth_transform: "SHA196",
+        windows_cipher_transform: "DES3",
+        windows_dh_group: "Group2",
+        windows_encryption_method: "DES3",
+        windows_integrity_method: "SHA1",
+        macos_encryption: "3DES",
+        macos_integrity: "SHA1-96",
+        macos_dh_group: 2,
+    },
+];
+
+/// Resolve the user-supplied ordered proposal names against `IKE_PROPOSALS`,
+/// falling back to the full built-in ladder when the caller didn't pin one
+/// (or none of the names they gave matched anything we know).
+fn resolve_ike_proposals(requested: &[String]) -> Vec<&'static IkeProposal> {
+    let matched: Vec<&'static IkeProposal> = requested
+        .iter()
+        .filter_map(|name| IKE_PROPOSALS.iter().find(|p| p.name == name))
+        .collect();
+    if matched.is_empty() {
+        IKE_PROPOSALS.iter().collect()
+    } else {
+        matched
+    }
+}
+
+/// Reverse-lookup a built-in proposal's name from the macOS security-
+/// association fields an IKEv2 `.mobileconfig` encodes them as — used by
+/// `profiles::import_mobileconfig` to turn an imported Apple profile's
+/// crypto settings back into the `ike_proposals` names `start_native_vpn`
+/// expects, the same names `resolve_ike_proposals` matches against.
+pub(crate) fn proposal_name_from_macos_params(encryption: &str, integrity: &str, dh_group: u32) -> Option<&'static str> {
+    IKE_PROPOSALS
+        .iter()
+        .find(|p| p.macos_encryption == encryption && p.macos_integrity == integrity && p.macos_dh_group == dh_group)
+        .map(|p| p.name)
+}
+
 #[tauri::command]
 async fn start_native_vpn(
     app: tauri::AppHandle,
@@ -1315,6 +1999,13 @@ async fn start_native_vpn(
     password: String,
     psk: String,
     auth_method: String,
+    ike_proposals: Option<Vec<String>>,
+    wg_private_key: Option<String>,
+    wg_address: Option<String>,
+    wg_dns: Option<String>,
+    wg_peer_public_key: Option<String>,
+    wg_allowed_ips: Option<String>,
+    wg_persistent_keepalive: Option<u64>,
 ) -> Result<(), String> {
     use std::process::Command;
     use std::thread;
@@ -1322,9 +2013,35 @@ async fn start_native_vpn(
     let app_data_dir = app.path().app_data_dir().expect("Failed to get app dir");
     let logs_path = app_data_dir.join("candy.logs");
 
+    if protocol == "openvpn" {
+        return start_native_openvpn(app, server, port, username, password, app_data_dir, logs_path).await;
+    }
+
+    if protocol == "wireguard" {
+        return start_native_wireguard(
+            app,
+            server,
+            port,
+            wg_private_key.unwrap_or_default(),
+            wg_address.unwrap_or_default(),
+            wg_dns.unwrap_or_default(),
+            wg_peer_public_key.unwrap_or_default(),
+            wg_allowed_ips.unwrap_or_else(|| "0.0.0.0/0, ::/0".to_string()),
+            wg_persistent_keepalive.unwrap_or(25),
+            app_data_dir,
+            logs_path,
+        ).await;
+    }
+
     let conn_name = format!("CandyConnect-{}", if protocol == "l2tp" { "L2TP" } else { "IKEv2" });
     let _ = append_log(&logs_path, "info", &format!("Starting native {} VPN: server={}, port={}, user={}", protocol.to_uppercase(), server, port, username));
 
+    let proposals = resolve_ike_proposals(&ike_proposals.unwrap_or_default());
+    let _ = append_log(&logs_path, "info", &format!(
+        "IKE/ESP proposal fallback order: {}",
+        proposals.iter().map(|p| p.name).collect::<Vec<_>>().join(" -> ")
+    ));
+
     #[cfg(target_os = "windows")]
     {
         use std::os::windows::process::CommandExt;
@@ -1370,45 +2087,61 @@ async fn start_native_vpn(
             // Non-fatal: profile might already exist
         }
 
-        // For L2TP, also set the PSK in the phonebook if needed
-        if protocol == "l2tp" && !psk.is_empty() {
-            let set_psk_cmd = format!(
-                "Set-VpnConnectionIPsecConfiguration -ConnectionName '{}' -AuthenticationTransformConstants SHA256128 -CipherTransformConstants AES128 -DHGroup Group14 -EncryptionMethod AES128 -IntegrityCheckMethod SHA256 -PfsGroup None -Force -ErrorAction SilentlyContinue",
-                conn_name
+        // 3. Set the IPsec proposal and connect, retrying down the fallback
+        // ladder if the server rejects one — a single fixed default (Group14) is
+        // what breaks against Windows RRAS, which only accepts modp1024.
+        // This applies to IKEv2 just as much as L2TP: -EncryptionLevel only
+        // picks a minimum strength, it doesn't pin a transform set, so
+        // without `Set-VpnConnectionIPsecConfiguration` IKEv2 negotiates
+        // whatever Windows defaults to and the fallback ladder never runs.
+        let mut connect_output = None;
+        let mut last_err = String::new();
+        let proposals_for_connect: Vec<&'static IkeProposal> = proposals.clone();
+
+        for proposal in &proposals_for_connect {
+            let set_config_cmd = format!(
+                "Set-VpnConnectionIPsecConfiguration -ConnectionName '{}' -AuthenticationTransformConstants {} -CipherTransformConstants {} -DHGroup {} -EncryptionMethod {} -IntegrityCheckMethod {} -PfsGroup None -Force -ErrorAction SilentlyContinue",
+                conn_name, proposal.windows_auth_transform, proposal.windows_cipher_transform,
+                proposal.windows_dh_group, proposal.windows_encryption_method, proposal.windows_integrity_method
             );
             let _ = Command::new("powershell")
-                .args(&["-NoProfile", "-Command", &set_psk_cmd])
+                .args(&["-NoProfile", "-Command", &set_config_cmd])
                 .creation_flags(0x08000000)
                 .output();
-        }
 
-        // 3. Connect using rasdial
-        let _ = append_log(&logs_path, "info", &format!("Connecting via rasdial: {} ...", conn_name));
-        let connect_output = Command::new("rasdial")
-            .args(&[&conn_name, &username, &password])
-            .creation_flags(0x08000000)
-            .output()
-            .map_err(|e| {
-                let msg = format!("rasdial failed to execute: {}", e);
-                let _ = append_log(&logs_path, "error", &msg);
-                msg
-            })?;
+            let _ = append_log(&logs_path, "info", &format!("Connecting via rasdial with proposal {}: {} ...", proposal.name, conn_name));
+            let output = Command::new("rasdial")
+                .args(&[&conn_name, &username, &password])
+                .creation_flags(0x08000000)
+                .output()
+                .map_err(|e| {
+                    let msg = format!("rasdial failed to execute: {}", e);
+                    let _ = append_log(&logs_path, "error", &msg);
+                    msg
+                })?;
 
-        if !connect_output.status.success() {
-            let stderr = String::from_utf8_lossy(&connect_output.stderr);
-            let stdout = String::from_utf8_lossy(&connect_output.stdout);
-            let err_msg = format!("{} connection failed: {} {}", protocol.to_uppercase(), stdout.trim(), stderr.trim());
-            let _ = append_log(&logs_path, "error", &err_msg);
+            if output.status.success() {
+                let _ = append_log(&logs_path, "info", &format!("{} connected with proposal {}", protocol.to_uppercase(), proposal.name));
+                connect_output = Some(output);
+                break;
+            }
+
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            last_err = format!("{} connection failed with proposal {}: {} {}", protocol.to_uppercase(), proposal.name, stdout.trim(), stderr.trim());
+            let _ = append_log(&logs_path, "warn", &last_err);
+        }
+
+        if connect_output.is_none() {
+            let _ = append_log(&logs_path, "error", &last_err);
             // Clean up the profile on failure
             let _ = Command::new("powershell")
                 .args(&["-NoProfile", "-Command", &format!("Remove-VpnConnection -Name '{}' -Force -ErrorAction SilentlyContinue", conn_name)])
                 .creation_flags(0x08000000)
                 .output();
-            return Err(err_msg);
+            return Err(last_err);
         }
 
-        let _ = append_log(&logs_path, "info", &format!("{} connected successfully via rasdial", protocol.to_uppercase()));
-
         // 4. Monitor the connection in background — emit vpn-disconnected when it drops
         let app_h = app.clone();
         let logs_p = logs_path.clone();
@@ -1439,129 +2172,155 @@ async fn start_native_vpn(
 
     #[cfg(target_os = "linux")]
     {
-        // Use nmcli (NetworkManager) for native VPN connections
-        // 1. Delete old connection if exists
-        let _ = Command::new("nmcli")
-            .args(&["connection", "delete", &conn_name])
-            .output();
+        // Drive charon directly via swanctl/VICI rather than depending on
+        // the NetworkManager-strongswan plugin, which is frequently missing
+        // on minimal installs.
+        return start_linux_native_strongswan(
+            app.clone(), protocol.clone(), server.clone(), username.clone(), password.clone(),
+            psk.clone(), auth_method.clone(), proposals.clone(), conn_name.clone(),
+            app_data_dir.clone(), logs_path.clone(),
+        ).await;
+    }
 
-        // 2. Create connection
+    #[cfg(target_os = "macos")]
+    {
+        // macOS: use scutil / networksetup for native VPN
         if protocol == "l2tp" {
-            let add_output = Command::new("nmcli")
-                .args(&[
-                    "connection", "add",
-                    "con-name", &conn_name,
-                    "type", "vpn",
-                    "vpn-type", "l2tp",
-                    "ifname", "--",
-                    &format!("vpn.data"), &format!("gateway={}, ipsec-enabled=yes, ipsec-psk={}, user={}", server, psk, username),
-                    &format!("vpn.secrets"), &format!("password={}", password),
-                ])
+            // Create L2TP VPN service
+            let create_output = Command::new("networksetup")
+                .args(&["-createnetworkservice", &conn_name, "L2TP"])
                 .output()
-                .map_err(|e| {
-                    let msg = format!("nmcli failed: {}. Is NetworkManager-l2tp installed?", e);
-                    let _ = append_log(&logs_path, "error", &msg);
-                    msg
-                })?;
-
-            if !add_output.status.success() {
-                // Fallback: try xl2tpd + ipsec directly
-                let _ = append_log(&logs_path, "warn", "nmcli l2tp failed, trying xl2tpd fallback...");
-
-                // Write xl2tpd client config
-                let l2tp_conf = format!(
-                    "[lac candyconnect]\nlns = {}\nppp debug = yes\npppoptfile = /tmp/cc-l2tp-options.txt\nlength bit = yes\n",
-                    server
-                );
-                let ppp_opts = format!(
-                    "ipcp-accept-local\nipcp-accept-remote\nrefuse-eap\nrequire-mschap-v2\nnoccp\nnoauth\nmtu 1400\nmru 1400\nnodefaultroute\nusepeerdns\nname {}\npassword {}\n",
-                    username, password
-                );
-                std::fs::write("/tmp/cc-l2tp-lac.conf", &l2tp_conf).map_err(|e| e.to_string())?;
-                std::fs::write("/tmp/cc-l2tp-options.txt", &ppp_opts).map_err(|e| e.to_string())?;
-
-                // Start IPSec
-                if !psk.is_empty() {
-                    let ipsec_secrets = format!("{} %any : PSK \"{}\"\n", server, psk);
-                    std::fs::write("/tmp/cc-ipsec.secrets", &ipsec_secrets).map_err(|e| e.to_string())?;
-                    let _ = Command::new("sudo").args(&["ipsec", "restart"]).output();
-                }
+                .map_err(|e| format!("networksetup failed: {}", e))?;
 
-                let _ = Command::new("sudo")
-                    .args(&["xl2tpd", "-c", "/tmp/cc-l2tp-lac.conf", "-D"])
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .map_err(|e| format!("Failed to start xl2tpd: {}", e))?;
+            // Configure the VPN
+            let _ = Command::new("networksetup")
+                .args(&["-setpppoeserveraddress", &conn_name, &server])
+                .output();
+            let _ = Command::new("networksetup")
+                .args(&["-setpppoeaccountname", &conn_name, &username])
+                .output();
 
-                thread::sleep(std::time::Duration::from_secs(1));
-                let _ = Command::new("sudo")
-                    .args(&["bash", "-c", "echo 'c candyconnect' > /var/run/xl2tpd/l2tp-control"])
+            // Set shared secret via security command
+            if !psk.is_empty() {
+                let _ = Command::new("security")
+                    .args(&["add-generic-password", "-a", &conn_name, "-s", "com.apple.net.racoon", "-w", &psk, "-T", "/usr/sbin/racoon"])
                     .output();
             }
-        } else {
-            // IKEv2 via nmcli + strongswan
-            let add_output = Command::new("nmcli")
-                .args(&[
-                    "connection", "add",
-                    "con-name", &conn_name,
-                    "type", "vpn",
-                    "vpn-type", "strongswan",
-                    "ifname", "--",
-                    &format!("vpn.data"), &format!("address={}, certificate=ignore, encap=no, esp=aes128-sha256, ike=aes256-sha256-modp2048, ipcomp=no, method={}, proposal=yes, virtual=yes",
-                        server, if auth_method == "cert" { "cert" } else { "eap" }),
-                    &format!("vpn.secrets"), &format!("password={}", password),
-                    &format!("vpn.user-name"), &username,
-                ])
+
+            // Connect
+            let connect_output = Command::new("networksetup")
+                .args(&["-connectpppoeservice", &conn_name])
                 .output()
-                .map_err(|e| {
-                    let msg = format!("nmcli failed: {}. Is NetworkManager-strongswan installed?", e);
-                    let _ = append_log(&logs_path, "error", &msg);
-                    msg
-                })?;
+                .map_err(|e| format!("L2TP connect failed: {}", e))?;
 
-            if !add_output.status.success() {
-                let stderr = String::from_utf8_lossy(&add_output.stderr);
-                let err_msg = format!("IKEv2 connection creation failed: {}", stderr.trim());
+            if !connect_output.status.success() {
+                let stderr = String::from_utf8_lossy(&connect_output.stderr);
+                let err_msg = format!("L2TP connection failed: {}", stderr.trim());
                 let _ = append_log(&logs_path, "error", &err_msg);
                 return Err(err_msg);
             }
-        }
+        } else {
+            // IKEv2 via scutil profiles. Installation happens through
+            // System Preferences' own UI (see `open` below), so unlike the
+            // Windows/Linux branches there's no synchronous failure to
+            // retry against — instead pin the most modern proposal from
+            // the fallback ladder and log it so a user hitting a rejected
+            // handshake knows which one to try pinning manually.
+            let proposal = proposals[0];
+            let _ = append_log(&logs_path, "info", &format!("macOS IKEv2: creating VPN profile via scutil with proposal {}...", proposal.name));
 
-        // 3. Activate the connection
-        let up_output = Command::new("nmcli")
-            .args(&["connection", "up", &conn_name])
-            .output()
-            .map_err(|e| format!("nmcli connection up failed: {}", e))?;
+            let profile_plist = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>PayloadContent</key>
+    <array>
+        <dict>
+            <key>IKEv2</key>
+            <dict>
+                <key>RemoteAddress</key>
+                <string>{}</string>
+                <key>AuthenticationMethod</key>
+                <string>{}</string>
+                <key>ExtendedAuthEnabled</key>
+                <true/>
+                <key>AuthName</key>
+                <string>{}</string>
+                <key>AuthPassword</key>
+                <string>{}</string>
+                <key>IKESecurityAssociationParameters</key>
+                <dict>
+                    <key>EncryptionAlgorithm</key>
+                    <string>{}</string>
+                    <key>IntegrityAlgorithm</key>
+                    <string>{}</string>
+                    <key>DiffieHellmanGroup</key>
+                    <integer>{}</integer>
+                </dict>
+                <key>ChildSecurityAssociationParameters</key>
+                <dict>
+                    <key>EncryptionAlgorithm</key>
+                    <string>{}</string>
+                    <key>IntegrityAlgorithm</key>
+                    <string>{}</string>
+                    <key>DiffieHellmanGroup</key>
+                    <integer>{}</integer>
+                </dict>
+            </dict>
+            <key>PayloadType</key>
+            <string>com.apple.vpn.managed</string>
+            <key>VPNType</key>
+            <string>IKEv2</string>
+        </dict>
+    </array>
+    <key>PayloadDisplayName</key>
+    <string>{}</string>
+    <key>PayloadType</key>
+    <string>Configuration</string>
+</dict>
+</plist>"#,
+                server, if auth_method == "cert" { "Certificate" } else { "None" }, username, password,
+                proposal.macos_encryption, proposal.macos_integrity, proposal.macos_dh_group,
+                proposal.macos_encryption, proposal.macos_integrity, proposal.macos_dh_group,
+                conn_name);
 
-        if !up_output.status.success() {
-            let stderr = String::from_utf8_lossy(&up_output.stderr);
-            let err_msg = format!("{} connection failed: {}", protocol.to_uppercase(), stderr.trim());
-            let _ = append_log(&logs_path, "error", &err_msg);
-            let _ = Command::new("nmcli").args(&["connection", "delete", &conn_name]).output();
-            return Err(err_msg);
-        }
+            let profile_path = app_data_dir.join("ikev2_profile.mobileconfig");
+            std::fs::write(&profile_path, &profile_plist).map_err(|e| e.to_string())?;
+
+            let install = Command::new("open")
+                .arg(&profile_path)
+                .output()
+                .map_err(|e| format!("Failed to install IKEv2 profile: {}", e))?;
 
-        let _ = append_log(&logs_path, "info", &format!("{} connected successfully via nmcli", protocol.to_uppercase()));
+            let _ = append_log(&logs_path, "info", "IKEv2 profile opened for installation. User needs to approve in System Preferences.");
+        }
 
-        // Monitor connection in background
+        // Monitor for macOS
         let app_h = app.clone();
         let logs_p = logs_path.clone();
         let conn_name_monitor = conn_name.clone();
         thread::spawn(move || {
             loop {
                 thread::sleep(std::time::Duration::from_secs(3));
-                let output = Command::new("nmcli")
-                    .args(&["-t", "-f", "NAME,TYPE", "connection", "show", "--active"])
+                let output = Command::new("scutil")
+                    .args(&["--nc", "list"])
                     .output();
                 match output {
                     Ok(o) => {
                         let stdout = String::from_utf8_lossy(&o.stdout);
-                        if !stdout.contains(&conn_name_monitor) {
-                            let _ = append_log(&logs_p, "warn", &format!("{} connection dropped", conn_name_monitor));
-                            use tauri::Emitter;
-                            let _ = app_h.emit("vpn-disconnected", ());
-                            break;
+                        // Check if our connection is listed and connected
+                        let is_connected = stdout.lines().any(|line| {
+                            line.contains(&conn_name_monitor) && line.contains("Connected")
+                        });
+                        if !is_connected {
+                            // Check if it was ever there (might still be connecting)
+                            let exists = stdout.contains(&conn_name_monitor);
+                            if exists {
+                                let _ = append_log(&logs_p, "warn", &format!("{} connection dropped", conn_name_monitor));
+                                use tauri::Emitter;
+                                let _ = app_h.emit("vpn-disconnected", ());
+                                break;
+                            }
                         }
                     }
                     Err(_) => {}
@@ -1570,130 +2329,743 @@ async fn start_native_vpn(
         });
     }
 
-    #[cfg(target_os = "macos")]
-    {
-        // macOS: use scutil / networksetup for native VPN
-        if protocol == "l2tp" {
-            // Create L2TP VPN service
-            let create_output = Command::new("networksetup")
-                .args(&["-createnetworkservice", &conn_name, "L2TP"])
-                .output()
-                .map_err(|e| format!("networksetup failed: {}", e))?;
+    engage_kill_switch_if_enabled(&server, &app_data_dir, &logs_path);
 
-            // Configure the VPN
-            let _ = Command::new("networksetup")
-                .args(&["-setpppoeserveraddress", &conn_name, &server])
-                .output();
-            let _ = Command::new("networksetup")
-                .args(&["-setpppoeaccountname", &conn_name, &username])
-                .output();
+    Ok(())
+}
 
-            // Set shared secret via security command
-            if !psk.is_empty() {
-                let _ = Command::new("security")
-                    .args(&["add-generic-password", "-a", &conn_name, "-s", "com.apple.net.racoon", "-w", &psk, "-T", "/usr/sbin/racoon"])
-                    .output();
-            }
+/// Native strongSwan backend for `start_native_vpn`'s Linux L2TP/IKEv2
+/// branches — replaces the NetworkManager-strongswan plugin (frequently
+/// missing on minimal installs) with charon driven directly: `swanctl`
+/// loads and initiates the connection, and a VICI client (`vici.rs`) polls
+/// charon's own SA state afterward instead of grepping
+/// `nmcli connection show --active`. L2TP rides on top of the IPsec layer
+/// as a transport-mode child — xl2tpd is only started once VICI confirms
+/// that child's SA is actually up.
+#[cfg(target_os = "linux")]
+async fn start_linux_native_strongswan(
+    app: tauri::AppHandle,
+    protocol: String,
+    server: String,
+    username: String,
+    password: String,
+    psk: String,
+    auth_method: String,
+    proposals: Vec<&'static IkeProposal>,
+    conn_name: String,
+    app_data_dir: std::path::PathBuf,
+    logs_path: std::path::PathBuf,
+) -> Result<(), String> {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    let run_dir = app_data_dir.join("strongswan");
+    fs::create_dir_all(&run_dir).map_err(|e| e.to_string())?;
+
+    // strongSwan accepts a comma-separated proposal list and negotiates
+    // down it itself, so the fallback ladder needs no retry loop here —
+    // unlike the Windows driver, which only gets to try one proposal per
+    // connection attempt.
+    let ike_list = proposals.iter().map(|p| p.ike).collect::<Vec<_>>().join(",");
+    let esp_list = proposals.iter().map(|p| p.esp).collect::<Vec<_>>().join(",");
+
+    let strongswan_conf_path = run_dir.join("strongswan.conf");
+    fs::write(&strongswan_conf_path, "charon {\n    filelog {\n        stderr {\n            default = 1\n        }\n    }\n    plugins {\n        vici {\n            load = yes\n        }\n    }\n}\n")
+        .map_err(|e| e.to_string())?;
+
+    let (local_auth, remote_auth, secrets_block) = if protocol == "l2tp" {
+        ("psk".to_string(), "psk".to_string(), format!("ike-{name} {{\n    id = {server}\n    secret = \"{psk}\"\n}}\n", name = conn_name, server = server, psk = psk))
+    } else {
+        let auth = if auth_method == "cert" { "pubkey" } else { "eap-mschapv2" };
+        (auth.to_string(), "pubkey".to_string(), format!("eap-{name} {{\n    id = {username}\n    secret = \"{password}\"\n}}\n", name = conn_name, username = username, password = password))
+    };
+
+    let children_block = if protocol == "l2tp" {
+        format!(
+            "            {name} {{\n                mode = transport\n                local_ts = dynamic[/1701]\n                remote_ts = dynamic[/1701]\n                esp_proposals = {esp}\n            }}\n",
+            name = conn_name, esp = esp_list
+        )
+    } else {
+        format!(
+            "            {name} {{\n                remote_ts = 0.0.0.0/0\n                esp_proposals = {esp}\n            }}\n",
+            name = conn_name, esp = esp_list
+        )
+    };
+
+    let swanctl_conf = format!(
+        "connections {{\n    {name} {{\n        version = {version}\n        remote_addrs = {server}\n        proposals = {ike}\n        local {{\n            auth = {local_auth}\n        }}\n        remote {{\n            auth = {remote_auth}\n        }}\n        children {{\n{children}        }}\n    }}\n}}\nsecrets {{\n    {secrets}}}\n",
+        name = conn_name,
+        version = if protocol == "l2tp" { 1 } else { 2 },
+        server = server,
+        ike = ike_list,
+        local_auth = local_auth,
+        remote_auth = remote_auth,
+        children = children_block,
+        secrets = secrets_block,
+    );
+    let swanctl_conf_path = run_dir.join("swanctl.conf");
+    fs::write(&swanctl_conf_path, &swanctl_conf).map_err(|e| e.to_string())?;
+
+    let _ = append_log(&logs_path, "info", &format!("Wrote swanctl.conf with proposal ladder: {}", ike_list));
+
+    // Launch charon directly — NetworkManager-strongswan just shells out to
+    // the system's ipsec service anyway, and that's frequently the piece
+    // missing on a minimal install.
+    let charon_bin = ["/usr/lib/ipsec/charon", "/usr/libexec/ipsec/charon", "/usr/lib/strongswan/charon"]
+        .iter()
+        .map(std::path::PathBuf::from)
+        .find(|p| p.exists())
+        .unwrap_or_else(|| std::path::PathBuf::from("charon"));
+
+    let mut charon_child = Command::new("sudo")
+        .env("STRONGSWAN_CONF", &strongswan_conf_path)
+        .arg(&charon_bin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            let msg = format!("CRITICAL: Failed to spawn charon: {}. Is strongSwan installed?", e);
+            let _ = append_log(&logs_path, "error", &msg);
+            msg
+        })?;
+
+    let _ = append_log(&logs_path, "info", &format!("charon spawned (PID: {})", charon_child.id()));
+
+    let strongswan_state = conn_state::StateWatcher::new(app.clone(), "strongswan");
+
+    let charon_stdout = charon_child.stdout.take().unwrap();
+    let charon_stderr = charon_child.stderr.take().unwrap();
+    let logs1 = logs_path.clone();
+    let strongswan_state_out = strongswan_state.clone();
+    thread::spawn(move || {
+        let reader = BufReader::new(charon_stdout);
+        for line in reader.lines() {
+            match line {
+                Ok(l) if !l.trim().is_empty() => {
+                    let _ = append_log(&logs1, "info", &format!("[charon] {}", l));
+                    strongswan_state_out.feed_line(&l, &conn_state::ReadyPatterns::STRONGSWAN);
+                }
+                Err(_) => break,
+                _ => {}
+            }
+        }
+    });
+    let logs2 = logs_path.clone();
+    let strongswan_state_err = strongswan_state.clone();
+    thread::spawn(move || {
+        let reader = BufReader::new(charon_stderr);
+        for line in reader.lines() {
+            match line {
+                Ok(l) if !l.trim().is_empty() => {
+                    let _ = append_log(&logs2, "error", &format!("[charon] {}", l));
+                    strongswan_state_err.feed_line(&l, &conn_state::ReadyPatterns::STRONGSWAN);
+                }
+                Err(_) => break,
+                _ => {}
+            }
+        }
+    });
+
+    if let Err(e) = wait_for_ready(&mut charon_child, || std::path::Path::new(vici::VICI_SOCKET).exists()) {
+        let err_msg = format!("charon's VICI socket never appeared: {}", e);
+        let _ = append_log(&logs_path, "error", &err_msg);
+        return Err(err_msg);
+    }
+
+    // swanctl still owns connection setup; only SA monitoring goes over
+    // VICI directly.
+    let load_output = Command::new("swanctl")
+        .args(&["--load-all", "--file", swanctl_conf_path.to_str().unwrap_or_default()])
+        .output()
+        .map_err(|e| format!("swanctl --load-all failed: {}", e))?;
+    if !load_output.status.success() {
+        let err_msg = format!("swanctl --load-all failed: {}", String::from_utf8_lossy(&load_output.stderr).trim());
+        let _ = append_log(&logs_path, "error", &err_msg);
+        return Err(err_msg);
+    }
+
+    let initiate_output = Command::new("swanctl")
+        .args(&["--initiate", "--child", &conn_name])
+        .output()
+        .map_err(|e| format!("swanctl --initiate failed: {}", e))?;
+    if !initiate_output.status.success() {
+        let err_msg = format!("IKE SA establishment failed: {}", String::from_utf8_lossy(&initiate_output.stderr).trim());
+        let _ = append_log(&logs_path, "error", &err_msg);
+        return Err(err_msg);
+    }
+
+    // Confirm the SA actually came up — resolve as soon as charon's own log
+    // reports `CHILD_SA ... established` (or a fatal line appears), falling
+    // back to polling VICI's `list-sas` directly rather than trusting
+    // swanctl's exit code alone.
+    if let Err(e) = conn_state::await_ready(&mut charon_child, &strongswan_state, READINESS_TIMEOUT, || vici_sa_is_up()) {
+        let err_msg = format!("IKE SA did not come up: {}", e);
+        let _ = append_log(&logs_path, "error", &err_msg);
+        return Err(err_msg);
+    }
+    let _ = append_log(&logs_path, "info", &format!("IKE SA established (proposal ladder: {})", ike_list));
+
+    // L2TP rides on top of the now-secured transport-mode SA — only start
+    // xl2tpd once VICI has confirmed the IPsec layer is actually up.
+    if protocol == "l2tp" {
+        let l2tp_conf = format!(
+            "[lac candyconnect]\nlns = {}\nppp debug = yes\npppoptfile = {}\nlength bit = yes\n",
+            server, run_dir.join("l2tp-options.txt").display()
+        );
+        let ppp_opts = format!(
+            "ipcp-accept-local\nipcp-accept-remote\nrefuse-eap\nrequire-mschap-v2\nnoccp\nnoauth\nmtu 1400\nmru 1400\nnodefaultroute\nusepeerdns\nname {}\npassword {}\n",
+            username, password
+        );
+        let l2tp_conf_path = run_dir.join("l2tp-lac.conf");
+        fs::write(&l2tp_conf_path, &l2tp_conf).map_err(|e| e.to_string())?;
+        fs::write(run_dir.join("l2tp-options.txt"), &ppp_opts).map_err(|e| e.to_string())?;
+
+        let mut xl2tpd_child = Command::new("sudo")
+            .arg("xl2tpd")
+            .arg("-c")
+            .arg(&l2tp_conf_path)
+            .arg("-D")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start xl2tpd: {}", e))?;
+
+        let pppd_state = conn_state::StateWatcher::new(app.clone(), "pppd");
+
+        let xl2tpd_stdout = xl2tpd_child.stdout.take().unwrap();
+        let xl2tpd_stderr = xl2tpd_child.stderr.take().unwrap();
+
+        let logs_x1 = logs_path.clone();
+        let pppd_state_out = pppd_state.clone();
+        let xl2tpd_stdout_thread = thread::spawn(move || {
+            let reader = BufReader::new(xl2tpd_stdout);
+            for line in reader.lines() {
+                match line {
+                    Ok(l) if !l.trim().is_empty() => {
+                        let _ = append_log(&logs_x1, "info", &format!("[xl2tpd] {}", l));
+                        pppd_state_out.feed_line(&l, &conn_state::ReadyPatterns::PPPD);
+                    }
+                    Err(_) => break,
+                    _ => {}
+                }
+            }
+        });
+
+        let logs_x2 = logs_path.clone();
+        let pppd_state_err = pppd_state.clone();
+        let xl2tpd_stderr_thread = thread::spawn(move || {
+            let reader = BufReader::new(xl2tpd_stderr);
+            for line in reader.lines() {
+                match line {
+                    Ok(l) if !l.trim().is_empty() => {
+                        let _ = append_log(&logs_x2, "error", &format!("[xl2tpd] {}", l));
+                        pppd_state_err.feed_line(&l, &conn_state::ReadyPatterns::PPPD);
+                    }
+                    Err(_) => break,
+                    _ => {}
+                }
+            }
+        });
+
+        // Actively wait for xl2tpd's control socket to exist instead of a
+        // blind sleep before writing the connect command to it.
+        if let Err(e) = wait_for_ready(&mut xl2tpd_child, || std::path::Path::new("/var/run/xl2tpd/l2tp-control").exists()) {
+            let _ = xl2tpd_stdout_thread.join();
+            let _ = xl2tpd_stderr_thread.join();
+            let err_msg = format!("xl2tpd did not become ready: {}", e);
+            let _ = append_log(&logs_path, "error", &err_msg);
+            return Err(err_msg);
+        }
+
+        let _ = Command::new("sudo")
+            .args(&["bash", "-c", "echo 'c candyconnect' > /var/run/xl2tpd/l2tp-control"])
+            .output();
+
+        // Readiness: resolve as soon as pppd's own log reports the local IP
+        // address it negotiated (or a fatal line appears), falling back to
+        // an active probe for the `ppp0` interface instead of a further
+        // blind sleep.
+        if let Err(e) = conn_state::await_ready(&mut xl2tpd_child, &pppd_state, READINESS_TIMEOUT, || tun_interface_ready()) {
+            let _ = xl2tpd_stdout_thread.join();
+            let _ = xl2tpd_stderr_thread.join();
+            let err_msg = format!("L2TP/PPP link did not come up: {}", e);
+            let _ = append_log(&logs_path, "error", &err_msg);
+            return Err(err_msg);
+        }
+
+        let _ = append_log(&logs_path, "info", "xl2tpd started on top of the established IPsec SA, PPP link up");
+    }
+
+    // Poll VICI for real SA state instead of grepping `nmcli connection
+    // show --active`, which only reflected NetworkManager's view of a
+    // connection it no longer owns.
+    let app_h = app.clone();
+    let logs_p = logs_path.clone();
+    let conn_name_monitor = conn_name.clone();
+    let strongswan_state_monitor = strongswan_state.clone();
+    thread::spawn(move || {
+        loop {
+            thread::sleep(std::time::Duration::from_secs(3));
+            if !vici_sa_is_up() {
+                let _ = append_log(&logs_p, "warn", &format!("{} IPsec SA dropped", conn_name_monitor));
+                strongswan_state_monitor.mark_disconnected();
+                use tauri::Emitter;
+                let _ = app_h.emit("vpn-disconnected", ());
+                break;
+            }
+        }
+    });
+
+    engage_kill_switch_if_enabled(&server, &app_data_dir, &logs_path);
+
+    Ok(())
+}
+
+/// Whether charon's reply to VICI's `list-sas` command, read live, decodes to
+/// any key at all. SA `state` values are not inspected.
+#[cfg(target_os = "linux")]
+fn vici_sa_is_up() -> bool {
+    match vici::ViciClient::connect(std::path::Path::new(vici::VICI_SOCKET)) {
+        Ok(mut client) => client.request("list-sas").map(|sa| !sa.is_empty()).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// OpenVPN driver for `start_native_vpn`'s `protocol == "openvpn"` branch.
+/// Unlike L2TP/IKEv2, which hand the connection off to the OS's own VPN
+/// manager (rasdial/nmcli/networksetup), OpenVPN has no first-class native
+/// client on any of these platforms — so this drives the `openvpn` binary
+/// directly through its management interface instead: it authenticates over
+/// the socket rather than an auth file, and tracks `>STATE:` lines for real
+/// connection health instead of polling `try_wait`.
+async fn start_native_openvpn(
+    app: tauri::AppHandle,
+    server: String,
+    port: u64,
+    username: String,
+    password: String,
+    app_data_dir: std::path::PathBuf,
+    logs_path: std::path::PathBuf,
+) -> Result<(), String> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    // Loopback-only management port for this connection; distinct from the
+    // Clash/Xray stats ports in `stats.rs` since this channel speaks
+    // OpenVPN's own line protocol, not HTTP.
+    const MANAGEMENT_PORT: u16 = 7520;
+
+    let _ = append_log(&logs_path, "info", &format!(
+        "Starting native OpenVPN: server={}, port={}, user={}", server, port, username
+    ));
+
+    #[allow(unused_variables)]
+    let resolve_tool = |base: &std::path::Path, rel_path: &str| -> std::path::PathBuf {
+        let p1 = base.join(rel_path);
+        if p1.exists() { return p1; }
+        let p2 = base.join("resources").join(rel_path);
+        if p2.exists() { return p2; }
+        p1
+    };
+    #[allow(unused_variables)]
+    let resource_dir = app.path().resource_dir().unwrap_or_else(|_| std::env::current_dir().unwrap());
+
+    #[cfg(target_os = "windows")]
+    let openvpn_bin = {
+        let bundled = resolve_tool(&resource_dir, "openvpn/openvpn.exe");
+        if bundled.exists() { bundled } else { std::path::PathBuf::from("openvpn.exe") }
+    };
+    #[cfg(not(target_os = "windows"))]
+    let openvpn_bin = std::path::PathBuf::from("openvpn");
+
+    // Minimal inline client config — there's no user-supplied .ovpn file in
+    // this flow (that's `start_openvpn`'s job), so generate just enough to
+    // reach the server and hand auth off to the management socket.
+    let ovpn_config = format!(
+        "client\ndev tun\nproto udp\nremote {} {}\nresolv-retry infinite\nnobind\npersist-key\npersist-tun\nauth-user-pass\nverb 3\nmanagement 127.0.0.1 {}\nmanagement-query-passwords\n",
+        server, port, MANAGEMENT_PORT
+    );
+    let ovpn_config_path = app_data_dir.join("native_openvpn.ovpn");
+    fs::write(&ovpn_config_path, &ovpn_config).map_err(|e| e.to_string())?;
+
+    let mut ovpn_cmd = Command::new(&openvpn_bin);
+    ovpn_cmd
+        .arg("--config")
+        .arg(&ovpn_config_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let user = std::env::var("USER").unwrap_or_default();
+        if user != "root" {
+            ovpn_cmd = Command::new("sudo");
+            ovpn_cmd
+                .arg(openvpn_bin.to_str().unwrap_or("openvpn"))
+                .arg("--config")
+                .arg(&ovpn_config_path)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        ovpn_cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    }
+
+    let mut ovpn_child = ovpn_cmd.spawn().map_err(|e| {
+        let msg = format!("CRITICAL: Failed to spawn OpenVPN: {}. Is openvpn installed?", e);
+        let _ = append_log(&logs_path, "error", &msg);
+        msg
+    })?;
+
+    let _ = append_log(&logs_path, "info", &format!("OpenVPN process spawned (PID: {})", ovpn_child.id()));
+
+    let ovpn_stdout = ovpn_child.stdout.take().unwrap();
+    let ovpn_stderr = ovpn_child.stderr.take().unwrap();
+
+    let logs1 = logs_path.clone();
+    thread::spawn(move || {
+        let reader = BufReader::new(ovpn_stdout);
+        for line in reader.lines() {
+            match line {
+                Ok(l) if !l.trim().is_empty() => {
+                    let _ = append_log(&logs1, "info", &format!("[OpenVPN] {}", l));
+                }
+                Err(_) => break,
+                _ => {}
+            }
+        }
+    });
+    let logs2 = logs_path.clone();
+    thread::spawn(move || {
+        let reader = BufReader::new(ovpn_stderr);
+        for line in reader.lines() {
+            match line {
+                Ok(l) if !l.trim().is_empty() => {
+                    let _ = append_log(&logs2, "error", &format!("[OpenVPN] {}", l));
+                }
+                Err(_) => break,
+                _ => {}
+            }
+        }
+    });
+
+    // The management listener doesn't exist until OpenVPN has parsed its
+    // config, so probe for it rather than dialing immediately.
+    if let Err(e) = wait_for_ready(&mut ovpn_child, || probe_tcp("127.0.0.1", MANAGEMENT_PORT)) {
+        let err_msg = format!("OpenVPN management interface never came up: {}", e);
+        let _ = append_log(&logs_path, "error", &err_msg);
+        use tauri::Emitter;
+        let _ = app.emit("vpn-disconnected", ());
+        return Err(err_msg);
+    }
+
+    let mgmt_stream = TcpStream::connect(("127.0.0.1", MANAGEMENT_PORT)).map_err(|e| {
+        let msg = format!("Failed to connect to OpenVPN management interface: {}", e);
+        let _ = append_log(&logs_path, "error", &msg);
+        msg
+    })?;
+    let mut mgmt_writer = mgmt_stream.try_clone().map_err(|e| e.to_string())?;
+
+    // Stream state/log events instead of polling: `state on` gets us
+    // `>STATE:` lines for CONNECTED/RECONNECTING/EXITING, `log on` gets us
+    // the PUSH_REPLY line we pull foreign_option_N-style directives out of.
+    let _ = writeln!(mgmt_writer, "state on");
+    let _ = writeln!(mgmt_writer, "log on");
+
+    let app_h = app.clone();
+    let logs_mgmt = logs_path.clone();
+    thread::spawn(move || {
+        let reader = BufReader::new(mgmt_stream);
+        let mut connected = false;
+
+        for line in reader.lines() {
+            let Ok(line) = line else { break };
+
+            if line.starts_with(">PASSWORD:Need 'Auth' username/password") {
+                let _ = writeln!(mgmt_writer, "username \"Auth\" \"{}\"", username);
+                let _ = writeln!(mgmt_writer, "password \"Auth\" \"{}\"", password);
+                continue;
+            }
+
+            if let Some(state) = line.strip_prefix(">STATE:") {
+                // <unix-time>,<state>,<desc>,<local-ip>,<remote-ip>,...
+                let fields: Vec<&str> = state.split(',').collect();
+                match fields.get(1).copied() {
+                    Some("CONNECTED") => {
+                        if !connected {
+                            connected = true;
+                            let _ = append_log(&logs_mgmt, "info", "OpenVPN management reports CONNECTED");
+                            use tauri::Emitter;
+                            let _ = app_h.emit("vpn-connected", ());
+                        }
+                    }
+                    Some("RECONNECTING") => {
+                        let reason = fields.get(2).copied().unwrap_or("unknown");
+                        let _ = append_log(&logs_mgmt, "warn", &format!("OpenVPN reconnecting: {}", reason));
+                    }
+                    Some("EXITING") => {
+                        let _ = append_log(&logs_mgmt, "warn", "OpenVPN management reports EXITING");
+                        use tauri::Emitter;
+                        let _ = app_h.emit("vpn-disconnected", ());
+                        break;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            // Pushed options arrive as a log line quoting the PUSH_REPLY
+            // control message, e.g. `PUSH_REPLY,dhcp-option DNS 1.1.1.1,
+            // redirect-gateway def1`.
+            if line.contains("PUSH_REPLY") {
+                for directive in line.split(',') {
+                    let directive = directive.trim();
+                    if let Some(dns_addr) = directive.strip_prefix("dhcp-option DNS ") {
+                        apply_pushed_dns(dns_addr.trim(), &logs_mgmt);
+                    } else if directive.starts_with("redirect-gateway") {
+                        let _ = append_log(&logs_mgmt, "info", "OpenVPN pushed redirect-gateway; routing all traffic through the tunnel");
+                    }
+                }
+            }
+        }
+
+        let _ = append_log(&logs_mgmt, "warn", "OpenVPN management channel closed");
+    });
+
+    // Watch the process itself in case it exits without ever sending
+    // EXITING on the management channel (e.g. it's killed outright).
+    let app_h2 = app.clone();
+    let logs_exit = logs_path.clone();
+    thread::spawn(move || {
+        let _ = ovpn_child.wait();
+        let _ = append_log(&logs_exit, "warn", "OpenVPN process exited");
+        use tauri::Emitter;
+        let _ = app_h2.emit("vpn-disconnected", ());
+    });
+
+    engage_kill_switch_if_enabled(&server, &app_data_dir, &logs_path);
+
+    Ok(())
+}
+
+/// Fixed interface/tunnel-service name for the native WireGuard driver, so
+/// `stop_vpn` (which has no session state of its own) can tear the tunnel
+/// down by name the same way it does for the L2TP/IKEv2 connection names.
+const WG_INTERFACE_NAME: &str = "cc-wg0";
+
+/// Window with no advancing handshake before the tunnel is considered dead.
+/// WireGuard peers re-handshake roughly every 2 minutes under active
+/// traffic, so this leaves about a minute of slack past one interval; a
+/// fully missed re-handshake (next one near 4 minutes) exceeds it.
+const WG_HANDSHAKE_STALE_WINDOW: std::time::Duration = std::time::Duration::from_secs(180);
+
+fn wg_config_path(app_data_dir: &std::path::Path) -> std::path::PathBuf {
+    app_data_dir.join("wireguard").join(format!("{}.conf", WG_INTERFACE_NAME))
+}
+
+/// Native WireGuard driver for `start_native_vpn`'s `protocol == "wireguard"`
+/// branch. Unlike L2TP/IKEv2/OpenVPN, there's no auth handshake to drive —
+/// the whole connection is described by one `wg-quick`-style config file, so
+/// this just writes it and hands it to the platform's own tunnel bring-up
+/// tool. Health afterward isn't a log line or a TCP probe like the other
+/// protocols: WireGuard reports no up/down state of its own, so the monitor
+/// below polls `wg show <iface> latest-handshakes` and treats a stalled
+/// handshake as the tunnel having died.
+async fn start_native_wireguard(
+    app: tauri::AppHandle,
+    endpoint_host: String,
+    endpoint_port: u64,
+    private_key: String,
+    address: String,
+    dns: String,
+    peer_public_key: String,
+    allowed_ips: String,
+    persistent_keepalive: u64,
+    app_data_dir: std::path::PathBuf,
+    logs_path: std::path::PathBuf,
+) -> Result<(), String> {
+    use std::process::Command;
+    use std::thread;
+
+    let _ = append_log(&logs_path, "info", &format!(
+        "Starting native WireGuard: endpoint={}:{}", endpoint_host, endpoint_port
+    ));
+
+    let conf_dir = app_data_dir.join("wireguard");
+    fs::create_dir_all(&conf_dir).map_err(|e| e.to_string())?;
+    let conf_path = wg_config_path(&app_data_dir);
+
+    let mut interface_block = format!(
+        "[Interface]\nPrivateKey = {}\nAddress = {}\n",
+        private_key, address
+    );
+    if !dns.is_empty() {
+        interface_block.push_str(&format!("DNS = {}\n", dns));
+    }
+
+    let peer_block = format!(
+        "[Peer]\nPublicKey = {}\nEndpoint = {}:{}\nAllowedIPs = {}\nPersistentKeepalive = {}\n",
+        peer_public_key, endpoint_host, endpoint_port, allowed_ips, persistent_keepalive
+    );
 
-            // Connect
-            let connect_output = Command::new("networksetup")
-                .args(&["-connectpppoeservice", &conn_name])
-                .output()
-                .map_err(|e| format!("L2TP connect failed: {}", e))?;
+    fs::write(&conf_path, format!("{}\n{}", interface_block, peer_block)).map_err(|e| e.to_string())?;
 
-            if !connect_output.status.success() {
-                let stderr = String::from_utf8_lossy(&connect_output.stderr);
-                let err_msg = format!("L2TP connection failed: {}", stderr.trim());
-                let _ = append_log(&logs_path, "error", &err_msg);
-                return Err(err_msg);
-            }
-        } else {
-            // IKEv2 via scutil profiles
-            let _ = append_log(&logs_path, "info", "macOS IKEv2: creating VPN profile via scutil...");
-            
-            let profile_plist = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-    <key>PayloadContent</key>
-    <array>
-        <dict>
-            <key>IKEv2</key>
-            <dict>
-                <key>RemoteAddress</key>
-                <string>{}</string>
-                <key>AuthenticationMethod</key>
-                <string>{}</string>
-                <key>ExtendedAuthEnabled</key>
-                <true/>
-                <key>AuthName</key>
-                <string>{}</string>
-                <key>AuthPassword</key>
-                <string>{}</string>
-            </dict>
-            <key>PayloadType</key>
-            <string>com.apple.vpn.managed</string>
-            <key>VPNType</key>
-            <string>IKEv2</string>
-        </dict>
-    </array>
-    <key>PayloadDisplayName</key>
-    <string>{}</string>
-    <key>PayloadType</key>
-    <string>Configuration</string>
-</dict>
-</plist>"#, server, if auth_method == "cert" { "Certificate" } else { "None" }, username, password, conn_name);
+    // Keys live in this file, same as any other wg-quick config — restrict
+    // it to the owner the way `wg-quick` itself expects.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = fs::set_permissions(&conf_path, fs::Permissions::from_mode(0o600));
+    }
 
-            let profile_path = app_data_dir.join("ikev2_profile.mobileconfig");
-            std::fs::write(&profile_path, &profile_plist).map_err(|e| e.to_string())?;
+    let _ = append_log(&logs_path, "info", &format!("Wrote WireGuard config to {}", conf_path.display()));
 
-            let install = Command::new("open")
-                .arg(&profile_path)
-                .output()
-                .map_err(|e| format!("Failed to install IKEv2 profile: {}", e))?;
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        // wg-quick already knows how to fall back to the bundled
+        // `wireguard-go` userspace implementation when the kernel module
+        // isn't present — asking for that explicitly here means this works
+        // the same way on a minimal install as it does on a fully set up
+        // one, without reimplementing wg-quick's own bring-up logic.
+        let up_output = Command::new("sudo")
+            .env("WG_QUICK_USERSPACE_IMPLEMENTATION", "wireguard-go")
+            .arg("wg-quick")
+            .arg("up")
+            .arg(&conf_path)
+            .output()
+            .map_err(|e| format!("Failed to run wg-quick: {}", e))?;
+        if !up_output.status.success() {
+            let err_msg = format!("wg-quick up failed: {}", String::from_utf8_lossy(&up_output.stderr).trim());
+            let _ = append_log(&logs_path, "error", &err_msg);
+            return Err(err_msg);
+        }
+        let _ = append_log(&logs_path, "info", &format!("wg-quick brought up interface {}", WG_INTERFACE_NAME));
+    }
 
-            let _ = append_log(&logs_path, "info", "IKEv2 profile opened for installation. User needs to approve in System Preferences.");
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        // The official WireGuard for Windows client's CLI installs a
+        // service-managed tunnel from a config file the same way wg-quick
+        // does on Linux/macOS — there's no wg-quick binary on Windows.
+        let install_output = Command::new("wireguard")
+            .arg("/installtunnelservice")
+            .arg(&conf_path)
+            .creation_flags(0x08000000)
+            .output()
+            .map_err(|e| format!("Failed to run wireguard /installtunnelservice: {}", e))?;
+        if !install_output.status.success() {
+            let err_msg = format!("WireGuard tunnel service install failed: {}", String::from_utf8_lossy(&install_output.stderr).trim());
+            let _ = append_log(&logs_path, "error", &err_msg);
+            return Err(err_msg);
         }
+        let _ = append_log(&logs_path, "info", &format!("Installed WireGuard tunnel service {}", WG_INTERFACE_NAME));
+    }
 
-        // Monitor for macOS
-        let app_h = app.clone();
-        let logs_p = logs_path.clone();
-        let conn_name_monitor = conn_name.clone();
-        thread::spawn(move || {
-            loop {
-                thread::sleep(std::time::Duration::from_secs(3));
-                let output = Command::new("scutil")
-                    .args(&["--nc", "list"])
-                    .output();
-                match output {
-                    Ok(o) => {
-                        let stdout = String::from_utf8_lossy(&o.stdout);
-                        // Check if our connection is listed and connected
-                        let is_connected = stdout.lines().any(|line| {
-                            line.contains(&conn_name_monitor) && line.contains("Connected")
-                        });
-                        if !is_connected {
-                            // Check if it was ever there (might still be connecting)
-                            let exists = stdout.contains(&conn_name_monitor);
-                            if exists {
-                                let _ = append_log(&logs_p, "warn", &format!("{} connection dropped", conn_name_monitor));
-                                use tauri::Emitter;
-                                let _ = app_h.emit("vpn-disconnected", ());
-                                break;
-                            }
-                        }
+    use tauri::Emitter;
+    let _ = app.emit("vpn-connected", ());
+
+    // Monitor: WireGuard has no connection-state events of its own, so the
+    // only real signal is whether the peer's handshake is still advancing.
+    let app_h = app.clone();
+    let logs_p = logs_path.clone();
+    thread::spawn(move || {
+        let mut last_handshake: Option<std::time::SystemTime> = None;
+        let mut last_change = std::time::Instant::now();
+        loop {
+            thread::sleep(std::time::Duration::from_secs(10));
+
+            match wg_handshake_status(WG_INTERFACE_NAME) {
+                WgHandshakeStatus::Handshaked(handshake) => {
+                    if last_handshake != Some(handshake) {
+                        last_handshake = Some(handshake);
+                        last_change = std::time::Instant::now();
                     }
-                    Err(_) => {}
+                }
+                // No handshake yet is expected for the first stretch of a
+                // fresh connection — leave `last_change` alone so it's held
+                // to the same stale-window grace period below as a handshake
+                // that stalled after succeeding once, instead of failing
+                // instantly.
+                WgHandshakeStatus::NeverHandshaked => {}
+                WgHandshakeStatus::InterfaceGone => {
+                    let _ = append_log(&logs_p, "warn", &format!("WireGuard interface {} is gone", WG_INTERFACE_NAME));
+                    let _ = app_h.emit("vpn-disconnected", ());
+                    break;
                 }
             }
-        });
-    }
+
+            if last_change.elapsed() >= WG_HANDSHAKE_STALE_WINDOW {
+                let _ = append_log(&logs_p, "warn", &format!(
+                    "WireGuard handshake stalled for {:?} — treating tunnel as down", last_change.elapsed()
+                ));
+                let _ = app_h.emit("vpn-disconnected", ());
+                break;
+            }
+        }
+    });
+
+    engage_kill_switch_if_enabled(&endpoint_host, &app_data_dir, &logs_path);
 
     Ok(())
 }
 
+/// Outcome of polling `wg show <iface> latest-handshakes` — kept distinct
+/// from a plain `Option` because "the interface is gone" (a hard failure,
+/// the tunnel is actually down) and "the interface exists but hasn't
+/// handshaked yet" (expected for the first stretch of a fresh connection)
+/// need different handling in the monitor loop below.
+enum WgHandshakeStatus {
+    Handshaked(std::time::SystemTime),
+    NeverHandshaked,
+    InterfaceGone,
+}
+
+/// Parse `wg show <iface> latest-handshakes`'s single-peer output line
+/// (`<peer public key>\t<unix timestamp, 0 if never>`). `wg` exits non-zero
+/// (or the line is unparseable) when the interface doesn't exist, which is
+/// the only case that should count as "gone" — a `0` timestamp just means no
+/// handshake has completed yet.
+fn wg_handshake_status(iface: &str) -> WgHandshakeStatus {
+    let Ok(output) = std::process::Command::new("wg").args(&["show", iface, "latest-handshakes"]).output() else {
+        return WgHandshakeStatus::InterfaceGone;
+    };
+    if !output.status.success() {
+        return WgHandshakeStatus::InterfaceGone;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let Some(timestamp) = stdout.lines().next().and_then(|l| l.split_whitespace().nth(1)).and_then(|s| s.parse::<u64>().ok()) else {
+        return WgHandshakeStatus::InterfaceGone;
+    };
+    if timestamp == 0 {
+        WgHandshakeStatus::NeverHandshaked
+    } else {
+        WgHandshakeStatus::Handshaked(std::time::UNIX_EPOCH + std::time::Duration::from_secs(timestamp))
+    }
+}
+
 #[tauri::command]
-async fn stop_vpn() -> Result<(), String> {
+async fn stop_vpn(app: tauri::AppHandle) -> Result<(), String> {
+    // Tell the active session's supervisor this is a deliberate disconnect so
+    // the kill commands below aren't mistaken for a crash and restarted.
+    if let Some(flag) = active_stop_flag().lock().unwrap().as_ref() {
+        flag.request_stop();
+    }
+
+    let app_data_dir = app.path().app_data_dir().expect("Failed to get app dir");
+    let wg_conf_path = wg_config_path(&app_data_dir);
+
+    // Deliberate disconnect always tears the kill switch down, whether or
+    // not it was ever engaged this session — a dropped tunnel is the one
+    // case it should stay up for. Same helper-first preference as engaging.
+    let _ = if helper::is_installed() { helper::disengage_kill_switch() } else { killswitch::disengage() };
+
     #[cfg(target_os = "windows")]
     {
         use std::process::Command;
@@ -1707,6 +3079,8 @@ async fn stop_vpn() -> Result<(), String> {
         let _ = Command::new("rasdial").args(&["CandyConnect-IKEv2", "/DISCONNECT"]).creation_flags(0x08000000).output();
         let _ = Command::new("powershell").args(&["-NoProfile", "-Command", "Remove-VpnConnection -Name 'CandyConnect-L2TP' -Force -ErrorAction SilentlyContinue"]).creation_flags(0x08000000).output();
         let _ = Command::new("powershell").args(&["-NoProfile", "-Command", "Remove-VpnConnection -Name 'CandyConnect-IKEv2' -Force -ErrorAction SilentlyContinue"]).creation_flags(0x08000000).output();
+        // Tear down the native WireGuard tunnel service, if installed
+        let _ = Command::new("wireguard").arg("/uninstalltunnelservice").arg(WG_INTERFACE_NAME).creation_flags(0x08000000).output();
     }
     #[cfg(target_os = "linux")]
     {
@@ -1722,6 +3096,17 @@ async fn stop_vpn() -> Result<(), String> {
         let _ = Command::new("nmcli").args(&["connection", "delete", "CandyConnect-IKEv2"]).output();
         // Also kill xl2tpd if running as fallback
         let _ = Command::new("pkill").arg("-9").arg("-x").arg("xl2tpd").spawn();
+        // charon is spawned directly (not via NetworkManager) by
+        // start_linux_native_strongswan, so nothing above stops it
+        let _ = Command::new("sudo").args(&["pkill", "-9", "-x", "charon"]).output();
+        // Tear down the native WireGuard tunnel, if up
+        if wg_conf_path.exists() {
+            let _ = Command::new("sudo")
+                .env("WG_QUICK_USERSPACE_IMPLEMENTATION", "wireguard-go")
+                .args(&["wg-quick", "down"])
+                .arg(&wg_conf_path)
+                .output();
+        }
     }
     #[cfg(target_os = "macos")]
     {
@@ -1733,6 +3118,14 @@ async fn stop_vpn() -> Result<(), String> {
         // Disconnect native VPN
         let _ = Command::new("networksetup").args(&["-disconnectpppoeservice", "CandyConnect-L2TP"]).output();
         let _ = Command::new("scutil").args(&["--nc", "stop", "CandyConnect-IKEv2"]).output();
+        // Tear down the native WireGuard tunnel, if up
+        if wg_conf_path.exists() {
+            let _ = Command::new("sudo")
+                .env("WG_QUICK_USERSPACE_IMPLEMENTATION", "wireguard-go")
+                .args(&["wg-quick", "down"])
+                .arg(&wg_conf_path)
+                .output();
+        }
     }
     Ok(())
 }
@@ -1744,23 +3137,13 @@ async fn write_log(app: tauri::AppHandle, level: String, message: String) -> Res
     append_log(&logs_path, &level, &message).map_err(|e| e.to_string())
 }
 
-fn append_log(path: &std::path::Path, level: &str, message: &str) -> std::io::Result<()> {
-    use std::fs::OpenOptions;
-    use std::io::Write;
-
-    let log_entry = serde_json::json!({
-        "timestamp": chrono::Local::now().to_rfc3339(),
-        "level": level,
-        "message": message
-    });
-
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path)?;
-
-    let line = format!("{}\n", log_entry.to_string());
-    file.write_all(line.as_bytes())?;
+/// `_path` is no longer written to directly — kept as a parameter so every
+/// existing call site (each protocol driver computes its own `logs_path`)
+/// didn't need to change. Persistence now goes through `tracing_log`'s
+/// rotating-file pipeline instead of one unbounded hand-appended file; see
+/// that module for the rotation/retention/verbosity behavior.
+fn append_log(_path: &std::path::Path, level: &str, message: &str) -> std::io::Result<()> {
+    tracing_log::log(level, message);
     Ok(())
 }
 
@@ -1810,7 +3193,8 @@ fn init_app_files(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
             "dnsttResolver": "auto",
             "dnsttProxyPort": 7070,
             "l2tpPsk": "",
-            "ikev2AuthMethod": "eap"
+            "ikev2AuthMethod": "eap",
+            "logVerbosity": "info"
         });
         fs::write(&settings_path, serde_json::to_string_pretty(&default_settings)?)?;
         log::info!("Created default settings.json");
@@ -1835,50 +3219,54 @@ fn init_app_files(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// TCP-handshake latency to `host:port`, averaged over `attempts` (default
+/// `latency::DEFAULT_ATTEMPTS`) connection attempts — replaces the old
+/// ICMP-`ping`-and-scrape-stdout approach, which the censored networks this
+/// app targets frequently block outright.
 #[tauri::command]
-async fn measure_latency(host: String) -> Result<u64, String> {
-    use std::process::Command;
-    
-    // Determine the ping command based on the OS
-    #[cfg(target_os = "windows")]
-    let mut cmd = {
-        use std::os::windows::process::CommandExt;
-        let mut c = Command::new("ping");
-        c.args(&["-n", "1", "-w", "2000", &host]);
-        c.creation_flags(0x08000000); // CREATE_NO_WINDOW
-        c
-    };
+async fn measure_latency(host: String, port: u16, attempts: Option<u8>) -> Result<latency::LatencyResult, String> {
+    latency::measure_tcp(&host, port, attempts.unwrap_or(latency::DEFAULT_ATTEMPTS))
+}
 
-    #[cfg(not(target_os = "windows"))]
-    let mut cmd = {
-        let mut c = Command::new("ping");
-        c.args(&["-c", "1", "-W", "2", &host]);
-        c
-    };
+/// Real end-to-end tunnel latency: an HTTP round trip through the
+/// already-running local proxy (`proxyHost`/`proxyPort` from
+/// `settings.json`, the same settings `generate_sing_box_config` reads),
+/// timed to first byte. Reflects what the selected core's traffic actually
+/// experiences, unlike `measure_latency`'s direct reachability check.
+#[tauri::command]
+async fn measure_proxy_delay(app: tauri::AppHandle) -> Result<u64, String> {
+    let app_data_dir = app.path().app_data_dir().expect("Failed to get app dir");
+    let settings_path = app_data_dir.join("settings.json");
+    let settings: serde_json::Value = fs::read_to_string(&settings_path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
 
-    let output = cmd.output().map_err(|e| e.to_string())?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    let proxy_host = settings["proxyHost"].as_str().unwrap_or("127.0.0.1");
+    let proxy_port = settings["proxyPort"].as_u64().unwrap_or(10808) as u16;
 
-    if output.status.success() {
-        // Parse "time=XXms" or "time=XX ms" from the output
-        for line in stdout.lines() {
-            if let Some(time_pos) = line.find("time=") {
-                let part = &line[time_pos + 5..];
-                // Handle cases like "time=14ms" or "time=14.2 ms"
-                let end_pos = part.find("ms").unwrap_or_else(|| {
-                    part.find(' ').unwrap_or(part.len())
-                });
-                let time_str = part[..end_pos].trim();
-                if let Ok(ms) = time_str.parse::<f64>() {
-                    return Ok(ms.round() as u64);
-                }
-            }
-        }
-        Err("Could not parse ping time".to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Ping failed: {} {}", stdout, stderr))
-    }
+    latency::measure_proxy_delay(proxy_host, proxy_port)
+}
+
+/// Resolve the tunnel's current exit country and cache it for
+/// `get_network_stats`'s `countryCode` field. Falls back to `"??"` (rather
+/// than erroring the command) whenever the lookup can't complete — the
+/// tunnel may be down, the egress probe blocked, or the bundled database
+/// missing, none of which should interrupt the caller.
+#[tauri::command]
+async fn resolve_exit_country(app: tauri::AppHandle) -> Result<String, String> {
+    let app_data_dir = app.path().app_data_dir().expect("Failed to get app dir");
+    let settings_path = app_data_dir.join("settings.json");
+    let settings: serde_json::Value = fs::read_to_string(&settings_path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+
+    let proxy_host = settings["proxyHost"].as_str().unwrap_or("127.0.0.1");
+    let proxy_port = settings["proxyPort"].as_u64().unwrap_or(10808) as u16;
+    let resource_dir = app.path().resource_dir().unwrap_or_else(|_| std::env::current_dir().unwrap());
+
+    Ok(geoip::resolve(proxy_host, proxy_port, &resource_dir).unwrap_or_else(|_| "??".to_string()))
 }
 
 #[tauri::command]
@@ -1916,29 +3304,27 @@ async fn check_system_executables(app: tauri::AppHandle) -> Result<Vec<String>,
     Ok(missing)
 }
 
+/// Whether the privileged helper service is installed — not whether the
+/// current process happens to be elevated, since the helper (not the GUI
+/// itself) is what needs root now. See `helper` for the install/uninstall
+/// commands this replaces repeated `restart_as_admin` re-elevation with.
 #[tauri::command]
 async fn is_admin() -> bool {
-    #[cfg(target_os = "windows")]
-    {
-        use std::process::Command;
-        use std::os::windows::process::CommandExt;
-        // CREATE_NO_WINDOW = 0x08000000
-        let output = Command::new("net")
-            .arg("session")
-            .creation_flags(0x08000000)
-            .output();
-        
-        match output {
-            Ok(out) => out.status.success(),
-            Err(_) => false,
-        }
-    }
+    helper::is_installed()
+}
 
-    #[cfg(not(target_os = "windows"))]
-    {
-        // On Unix-like systems, check if UID is 0
-        unsafe { libc::getuid() == 0 }
-    }
+/// Install the privileged helper service (systemd unit on Linux, Windows
+/// Service on Windows). Still needs one elevated prompt to install — that's
+/// unavoidable — but every privileged action afterwards talks to the
+/// already-running helper instead of re-elevating the whole GUI.
+#[tauri::command]
+async fn install_helper() -> Result<(), String> {
+    helper::install()
+}
+
+#[tauri::command]
+async fn uninstall_helper() -> Result<(), String> {
+    helper::uninstall()
 }
 
 #[tauri::command]
@@ -1973,27 +3359,6 @@ async fn restart_as_admin(app: tauri::AppHandle) -> Result<(), String> {
     }
 }
 
-/// Snapshot of network interface byte counters at a point in time.
-struct NetSnapshot {
-    bytes_recv: u64,
-    bytes_sent: u64,
-    timestamp: std::time::Instant,
-}
-
-use std::sync::OnceLock;
-
-/// Global state for tracking network deltas between calls.
-fn net_state() -> &'static Mutex<Option<NetSnapshot>> {
-    static STATE: OnceLock<Mutex<Option<NetSnapshot>>> = OnceLock::new();
-    STATE.get_or_init(|| Mutex::new(None))
-}
-
-/// Session-level cumulative counters (reset when the client reconnects).
-fn net_session() -> &'static Mutex<(u64, u64)> {
-    static SESSION: OnceLock<Mutex<(u64, u64)>> = OnceLock::new();
-    SESSION.get_or_init(|| Mutex::new((0, 0)))
-}
-
 /// VPN interface prefixes we want to track. Only these carry VPN traffic.
 /// - tun*     : OpenVPN, WireGuard (wg-quick), sing-box TUN, IKEv2
 /// - wg*      : WireGuard kernel interface
@@ -2010,10 +3375,65 @@ fn is_vpn_interface(name: &str) -> bool {
         || n.starts_with("candy")
 }
 
+/// Same VPN-marker list `is_vpn_interface` checks as prefixes of a short
+/// kernel interface name (`tun0`, `wg0`, ...), but matched as a substring
+/// instead — Windows adapter `Description`/`Alias` strings are verbose
+/// ("WireGuard Tunnel", "TAP-Windows Adapter V9") rather than short kernel
+/// names, so a prefix check would miss them the way the old PowerShell
+/// branch's `-match 'tun|wg|vpn|tap|candyconnect|sing'` regex didn't.
+#[cfg(target_os = "windows")]
+pub(crate) fn windows_adapter_name_matches_vpn(name: &str) -> bool {
+    let n = name.to_lowercase();
+    ["tun", "wg", "vpn", "tap", "candyconnect", "sing"]
+        .iter()
+        .any(|marker| n.contains(marker))
+}
+
+/// Native `GetIfTable2` walk, summing `InOctets`/`OutOctets` across adapters
+/// whose `Description` or `Alias` looks like one of ours. Replaces spawning
+/// PowerShell/`netstat` on every poll tick (`get_network_stats` is called
+/// about once a second) with a single in-process API call.
+#[cfg(target_os = "windows")]
+fn windows_vpn_interface_counters() -> Option<(u64, u64)> {
+    use windows::Win32::NetworkManagement::IpHelper::{FreeMibTable, GetIfTable2, MIB_IF_TABLE2};
+
+    unsafe {
+        let mut table: *mut MIB_IF_TABLE2 = std::ptr::null_mut();
+        if GetIfTable2(&mut table).is_err() || table.is_null() {
+            return None;
+        }
+
+        let num_entries = (*table).NumEntries as usize;
+        let rows = std::slice::from_raw_parts((*table).Table.as_ptr(), num_entries);
+
+        let mut recv: u64 = 0;
+        let mut sent: u64 = 0;
+        let mut found = false;
+        for row in rows {
+            let description = String::from_utf16_lossy(&row.Description)
+                .trim_end_matches('\0')
+                .to_string();
+            let alias = String::from_utf16_lossy(&row.Alias)
+                .trim_end_matches('\0')
+                .to_string();
+            if windows_adapter_name_matches_vpn(&description) || windows_adapter_name_matches_vpn(&alias) {
+                recv += row.InOctets;
+                sent += row.OutOctets;
+                found = true;
+            }
+        }
+
+        FreeMibTable(table as *const _);
+
+        if found { Some((recv, sent)) } else { None }
+    }
+}
+
 /// Read bytes_recv and bytes_sent across VPN interfaces only.
 /// Falls back to all-interface totals if no VPN interface is found (not connected).
-/// Platform-specific implementation.
-fn read_net_counters() -> Option<(u64, u64)> {
+/// Platform-specific implementation. `pub(crate)` so `net_stats`'s
+/// background sampler can call it without duplicating the per-OS logic.
+pub(crate) fn read_net_counters() -> Option<(u64, u64)> {
     #[cfg(target_os = "linux")]
     {
         // Read from /proc/net/dev — only sum VPN interfaces
@@ -2048,45 +3468,25 @@ fn read_net_counters() -> Option<(u64, u64)> {
 
     #[cfg(target_os = "windows")]
     {
+        // Native IP Helper call instead of spawning PowerShell/netstat on
+        // every ~1s poll tick — `get_network_stats` is called that often,
+        // and a subprocess costs ~100-300ms of startup latency per sample,
+        // which both slows the UI and skews the delta math it's used for.
+        if let Some(counters) = windows_vpn_interface_counters() {
+            return Some(counters);
+        }
+        // Fallback: total across every adapter via netstat -e, when no VPN
+        // adapter matched or GetIfTable2 itself failed (e.g. running under
+        // a restricted token) — not VPN-scoped, but better than nothing.
         use std::process::Command;
         use std::os::windows::process::CommandExt;
-
-        // Use `netsh interface ipv4 show interfaces` to enumerate interfaces and
-        // find VPN/TAP adapters (TUN/TAP from sing-box / WireGuard / OpenVPN).
-        // These typically appear as adapters with "VPN", "tun", "wg", "TAP" in their name.
-        // We use `Get-NetAdapterStatistics` via PowerShell for precision.
-        let output = Command::new("powershell")
-            .args(&[
-                "-NoProfile", "-NonInteractive", "-Command",
-                "Get-NetAdapterStatistics | Where-Object { $_.Name -match 'tun|wg|vpn|tap|candyconnect|sing' -or (Get-NetAdapter -Name $_.Name -ErrorAction SilentlyContinue).InterfaceDescription -match 'tun|tap|wintun|wireguard|sing' } | Measure-Object -Property ReceivedBytes,SentBytes -Sum | Select-Object -Property Property,Sum | ConvertTo-Csv -NoTypeInformation",
-            ])
+        let output = Command::new("netstat")
+            .args(&["-e"])
             .creation_flags(0x08000000)
             .output()
             .ok()?;
         let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut recv: u64 = 0;
-        let mut sent: u64 = 0;
         for line in stdout.lines() {
-            let parts: Vec<&str> = line.trim_matches('"').split("\",\"").collect();
-            if parts.len() >= 2 {
-                let prop = parts[0].trim_matches('"');
-                let val: u64 = parts[1].trim_matches('"').parse().unwrap_or(0);
-                if prop == "ReceivedBytes" { recv = val; }
-                if prop == "SentBytes" { sent = val; }
-            }
-        }
-        if recv > 0 || sent > 0 {
-            return Some((recv, sent));
-        }
-        // Fallback: try reading just the WinTUN/TAP adapter via netstat -e
-        // (netstat -e gives totals for ALL adapters; not ideal but better than nothing)
-        let output2 = Command::new("netstat")
-            .args(&["-e"])
-            .creation_flags(0x08000000)
-            .output()
-            .ok()?;
-        let stdout2 = String::from_utf8_lossy(&output2.stdout);
-        for line in stdout2.lines() {
             let line = line.trim();
             if line.starts_with("Bytes") {
                 let parts: Vec<&str> = line.split_whitespace().collect();
@@ -2135,64 +3535,136 @@ fn read_net_counters() -> Option<(u64, u64)> {
     }
 }
 
+/// One-shot read of the background sampler's latest snapshot. Actual
+/// counter reads now happen on `net_stats::spawn`'s own schedule rather than
+/// inline here — see that module for the smoothing and session-total logic.
 #[tauri::command]
-async fn get_network_stats() -> Result<serde_json::Value, String> {
-    let counters = read_net_counters().ok_or("Failed to read network counters")?;
-    let now = std::time::Instant::now();
-    let (bytes_recv, bytes_sent) = counters;
-
-    let mut state = net_state().lock().map_err(|e| e.to_string())?;
-    let mut session = net_session().lock().map_err(|e| e.to_string())?;
-
-    let (dl_kbps, ul_kbps) = if let Some(prev) = state.as_ref() {
-        let elapsed = now.duration_since(prev.timestamp).as_secs_f64();
-        if elapsed > 0.01 {
-            let dl_bytes = bytes_recv.saturating_sub(prev.bytes_recv);
-            let ul_bytes = bytes_sent.saturating_sub(prev.bytes_sent);
-
-            // Accumulate session totals
-            session.0 += dl_bytes;
-            session.1 += ul_bytes;
-
-            let dl = (dl_bytes as f64 / elapsed) / 1024.0;
-            let ul = (ul_bytes as f64 / elapsed) / 1024.0;
-            (dl, ul)
-        } else {
-            (0.0, 0.0)
-        }
-    } else {
-        // First call — no delta yet, just record baseline
-        (0.0, 0.0)
+async fn get_network_stats() -> Result<net_stats::NetworkStats, String> {
+    Ok(net_stats::latest())
+}
+
+#[tauri::command]
+async fn reset_network_session() -> Result<(), String> {
+    net_stats::request_reset();
+    Ok(())
+}
+
+/// Pause or resume the `vpn-stats` polling loop without affecting the VPN
+/// connection itself.
+#[tauri::command]
+async fn set_stats_polling(enabled: bool) -> Result<(), String> {
+    stats::set_polling_enabled(enabled);
+    Ok(())
+}
+
+/// Fetch a one-shot traffic snapshot (up/down totals, rate is 0 since there's
+/// no prior sample to diff against — use the `vpn-stats` event stream for
+/// rates).
+#[tauri::command]
+async fn get_stats_snapshot(app: tauri::AppHandle) -> Result<serde_json::Value, String> {
+    let resource_dir = app.path().resource_dir().unwrap_or_else(|_| std::env::current_dir().unwrap());
+    let xray_bin = {
+        let p1 = resource_dir.join(if cfg!(target_os = "windows") { "xray/xray.exe" } else { "xray/xray" });
+        let p2 = resource_dir.join("resources").join(if cfg!(target_os = "windows") { "xray/xray.exe" } else { "xray/xray" });
+        if p1.exists() { p1 } else { p2 }
     };
+    let xray_bin = xray_bin.exists().then_some(xray_bin);
+    let snapshot = stats::snapshot_now(xray_bin.as_deref(), Some(stats::CLASH_API_ADDR), stats::XRAY_STATS_API_ADDR);
+    serde_json::to_value(snapshot).map_err(|e| e.to_string())
+}
 
-    // Store current snapshot
-    *state = Some(NetSnapshot {
-        bytes_recv,
-        bytes_sent,
-        timestamp: now,
-    });
+/// Query recent structured log events from the in-memory ring buffer,
+/// optionally filtered by exact `level` (e.g. "error") and/or `source`
+/// ("xray", "sing-box", "orchestrator"), capped to the most recent `limit`.
+#[tauri::command]
+async fn query_log_events(
+    level: Option<String>,
+    source: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<log_events::LogEvent>, String> {
+    Ok(log_events::query(level.as_deref(), source.as_deref(), limit))
+}
 
-    Ok(serde_json::json!({
-        "downloadSpeed": (dl_kbps * 10.0).round() / 10.0,
-        "uploadSpeed": (ul_kbps * 10.0).round() / 10.0,
-        "totalDownload": session.0,
-        "totalUpload": session.1,
-        "countryCode": "??",
-    }))
+/// Export the full ring buffer as JSON-lines, for the frontend to offer as a
+/// downloadable diagnostics bundle.
+#[tauri::command]
+async fn export_log_events() -> Result<String, String> {
+    Ok(log_events::export_jsonlines())
 }
 
+/// Query persisted log entries across the rotated `candy.log.*` files on
+/// disk, filtered by exact `level`, a case-insensitive `contains` substring
+/// of `message`, and/or a half-open `[since, until)` RFC3339 timestamp
+/// range, capped to the most recent `limit` matches. Complements
+/// `query_log_events`, which only sees the bounded in-memory ring buffer —
+/// this reaches back over every rotated file still on disk (the last
+/// `MAX_LOG_FILES` daily files `tracing_log` keeps).
 #[tauri::command]
-async fn reset_network_session() -> Result<(), String> {
-    let mut session = net_session().lock().map_err(|e| e.to_string())?;
-    *session = (0, 0);
-    // Also reset the baseline snapshot so the first read after reset shows 0 speed
-    let mut state = net_state().lock().map_err(|e| e.to_string())?;
-    *state = None;
+async fn query_logs(
+    app: tauri::AppHandle,
+    level: Option<String>,
+    contains: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<tracing_log::PersistedLogEntry>, String> {
+    let app_data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    Ok(tracing_log::query(
+        &app_data_dir,
+        level.as_deref(),
+        contains.as_deref(),
+        since.as_deref(),
+        until.as_deref(),
+        limit,
+    ))
+}
+
+/// Let the user dial persisted log detail up or down from the settings UI
+/// without restarting the app — `settings.json`'s `logVerbosity` is only
+/// read at startup, so the frontend calls this alongside saving the setting.
+#[tauri::command]
+async fn set_log_verbosity(level: String) -> Result<(), String> {
+    tracing_log::set_verbosity(&level);
     Ok(())
 }
 
+/// Serialize a connection profile to its portable JSON form. The frontend
+/// writes the returned string wherever the user chooses to save it.
+#[tauri::command]
+async fn export_vpn_profile(profile: profiles::VpnProfile) -> Result<String, String> {
+    profiles::export(&profile)
+}
+
+/// Import a previously exported native profile (a `.ccprofile`'s contents).
+#[tauri::command]
+async fn import_vpn_profile(contents: String) -> Result<profiles::VpnProfile, String> {
+    profiles::import_native(&contents)
+}
+
+/// Import an external profile format, mapping it onto the same fields a
+/// native profile has. `format` is one of `"ovpn"`, `"ikev2_params"`, or
+/// `"mobileconfig"`.
+#[tauri::command]
+async fn import_external_vpn_profile(format: String, contents: String) -> Result<profiles::VpnProfile, String> {
+    match format.as_str() {
+        "ovpn" => profiles::import_ovpn(&contents),
+        "ikev2_params" => profiles::import_ikev2_params(&contents),
+        "mobileconfig" => profiles::import_mobileconfig(&contents),
+        other => Err(format!("Unknown profile format: {}", other)),
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+  // Act as the privileged helper instead of launching the GUI when started
+  // by the systemd unit/Windows Service `helper::install` registers.
+  if std::env::args().any(|a| a == helper::HELPER_ARG) {
+    if let Err(e) = helper::serve() {
+      log::error!("Helper service exited: {}", e);
+    }
+    return;
+  }
+
   tauri::Builder::default()
     .plugin(tauri_plugin_fs::init())
     .setup(|app| {
@@ -2209,6 +3681,27 @@ pub fn run() {
         log::error!("Failed to initialize app files: {}", e);
       }
 
+      // Rotating `tracing` pipeline for persisted logs, replacing
+      // `append_log`'s old unbounded single-file writer. Verbosity comes
+      // from `settings.json` the same way `language` already does there.
+      {
+        let app_data_dir = app.path().app_data_dir().expect("Failed to get app data directory");
+        let settings_path = app_data_dir.join("settings.json");
+        let verbosity = fs::read_to_string(&settings_path)
+          .ok()
+          .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+          .and_then(|v| v["logVerbosity"].as_str().map(|s| s.to_string()))
+          .unwrap_or_else(|| "info".to_string());
+        tracing_log::init(&app_data_dir, &verbosity);
+      }
+
+      // Let append_log emit structured `log-event`s to the frontend.
+      log_events::set_emitter(app.handle().clone());
+
+      // Background interface byte-counter sampler — runs for the app's
+      // lifetime, independent of VPN connection state.
+      net_stats::spawn(app.handle().clone());
+
       // System Tray Setup
       let show_i = MenuItem::with_id(app, "show", "Show CandyConnect", true, None::<&str>)?;
       let quit_i = MenuItem::with_id(app, "quit", "Exit App", true, None::<&str>)?;
@@ -2243,7 +3736,7 @@ pub fn run() {
 
       Ok(())
     })
-    .invoke_handler(tauri::generate_handler![measure_latency, check_system_executables, is_admin, restart_as_admin, generate_sing_box_config, start_vpn, start_dnstt, start_native_vpn, start_wireguard, start_openvpn, stop_vpn, write_log, get_network_stats, reset_network_session])
+    .invoke_handler(tauri::generate_handler![measure_latency, measure_proxy_delay, resolve_exit_country, check_system_executables, is_admin, restart_as_admin, install_helper, uninstall_helper, generate_sing_box_config, start_vpn, start_dnstt, start_native_vpn, start_wireguard, start_openvpn, stop_vpn, write_log, get_network_stats, reset_network_session, set_stats_polling, get_stats_snapshot, query_log_events, export_log_events, query_logs, set_log_verbosity, export_vpn_profile, import_vpn_profile, import_external_vpn_profile])
     .build(tauri::generate_context!())
     .expect("error while building tauri application")
     .run(|app_handle, event| match event {
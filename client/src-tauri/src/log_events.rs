@@ -0,0 +1,112 @@
+//! Structured log events kept alongside `append_log`'s persisted JSON-lines log.
+//!
+//! `append_log` already persists `{timestamp, level, message}` (through
+//! `tracing_log`, into the rotated `candy.log.*` files),
+//! but that's append-only and untyped from the frontend's point of view —
+//! there's no way to filter by severity or tell Xray's output apart from
+//! Sing-box's or the orchestrator's own messages without scraping the
+//! `[Xray]`/`[Sing-box]` prefixes baked into `message`. `record` keeps a
+//! bounded in-memory ring buffer of the same events (tagged with an inferred
+//! `source`) and emits each one to the frontend as `log-event`, so the UI
+//! can filter and stream without re-reading the log file.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+
+/// How many recent events the ring buffer keeps before dropping the oldest.
+const MAX_EVENTS: usize = 2000;
+
+#[derive(Clone, Serialize)]
+pub struct LogEvent {
+    pub timestamp: String,
+    pub level: String,
+    pub source: String,
+    pub message: String,
+}
+
+fn buffer() -> &'static Mutex<VecDeque<LogEvent>> {
+    static BUFFER: OnceLock<Mutex<VecDeque<LogEvent>>> = OnceLock::new();
+    BUFFER.get_or_init(|| Mutex::new(VecDeque::with_capacity(MAX_EVENTS)))
+}
+
+fn emitter() -> &'static Mutex<Option<tauri::AppHandle>> {
+    static EMITTER: OnceLock<Mutex<Option<tauri::AppHandle>>> = OnceLock::new();
+    EMITTER.get_or_init(|| Mutex::new(None))
+}
+
+/// Registered once from `run()`'s `.setup()` so `record` — called from deep
+/// inside watcher threads that don't carry an `AppHandle` of their own — can
+/// still emit to the frontend.
+pub fn set_emitter(app: tauri::AppHandle) {
+    *emitter().lock().unwrap() = Some(app);
+}
+
+/// Existing call sites tag the child process's output with a `[Xray]` /
+/// `[Sing-box]` prefix rather than a separate field — reuse that instead of
+/// touching every `append_log` call site to pass a source explicitly.
+fn infer_source(message: &str) -> &'static str {
+    if message.contains("[Xray]") {
+        "xray"
+    } else if message.contains("[Sing-box]") {
+        "sing-box"
+    } else {
+        "orchestrator"
+    }
+}
+
+/// Push a structured event onto the ring buffer and emit it as `log-event`.
+/// Called from `tracing_log::log` (which `append_log` goes through)
+/// alongside (not instead of) the file write.
+pub fn record(level: &str, message: &str) {
+    let event = LogEvent {
+        timestamp: chrono::Local::now().to_rfc3339(),
+        level: level.to_string(),
+        source: infer_source(message).to_string(),
+        message: message.to_string(),
+    };
+
+    {
+        let mut buf = buffer().lock().unwrap();
+        if buf.len() >= MAX_EVENTS {
+            buf.pop_front();
+        }
+        buf.push_back(event.clone());
+    }
+
+    if let Some(app) = emitter().lock().unwrap().as_ref() {
+        use tauri::Emitter;
+        let _ = app.emit("log-event", &event);
+    }
+}
+
+/// Query the ring buffer, optionally filtered by exact `level`/`source`
+/// match. `limit` caps the result to the most recent N matches.
+pub fn query(level: Option<&str>, source: Option<&str>, limit: Option<usize>) -> Vec<LogEvent> {
+    let filtered: Vec<LogEvent> = buffer()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| level.map_or(true, |l| e.level == l))
+        .filter(|e| source.map_or(true, |s| e.source == s))
+        .cloned()
+        .collect();
+
+    match limit {
+        Some(n) if n < filtered.len() => filtered[filtered.len() - n..].to_vec(),
+        _ => filtered,
+    }
+}
+
+/// Export the full ring buffer as JSON-lines, one `LogEvent` per line. The
+/// persisted `candy.log.*` lines share the `timestamp`/`level`/`message`
+/// keys but have no `source`, and write `level` upper-case.
+pub fn export_jsonlines() -> String {
+    buffer()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|e| serde_json::to_string(e).ok())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,144 @@
+//! Background sampler for VPN interface byte counters.
+//!
+//! `get_network_stats` used to be pull-based: the frontend invoked it on a
+//! UI timer, and every invocation read the OS interface counters inline, so
+//! sampling cadence (and, on Windows, subprocess cost) was coupled to
+//! however jittery that timer was. `spawn` instead runs once, for the
+//! lifetime of the app, as a task on the Tauri async runtime: it reads
+//! `read_net_counters()` on a fixed interval, smooths the resulting
+//! download/upload speeds with an exponential moving average so the
+//! readout doesn't flicker between ticks, and emits the result to the
+//! webview as `network-stats`. `get_network_stats` and
+//! `reset_network_session` become thin wrappers around this task's state
+//! instead of doing the read/reset themselves.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::Emitter;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Weight given to each new sample in the exponential moving average — low
+/// enough that one noisy tick doesn't visibly jump the readout, high enough
+/// that a real change in throughput still shows up within a couple of
+/// seconds.
+const EMA_ALPHA: f64 = 0.3;
+
+#[derive(Clone, Serialize, Default)]
+pub struct NetworkStats {
+    #[serde(rename = "downloadSpeed")]
+    pub download_speed: f64,
+    #[serde(rename = "uploadSpeed")]
+    pub upload_speed: f64,
+    #[serde(rename = "totalDownload")]
+    pub total_download: u64,
+    #[serde(rename = "totalUpload")]
+    pub total_upload: u64,
+    #[serde(rename = "countryCode")]
+    pub country_code: String,
+}
+
+struct Sampler {
+    prev: Option<(u64, u64, Instant)>,
+    session_download: u64,
+    session_upload: u64,
+    ema_download_kbps: f64,
+    ema_upload_kbps: f64,
+    last: NetworkStats,
+}
+
+impl Sampler {
+    fn new() -> Self {
+        Sampler {
+            prev: None,
+            session_download: 0,
+            session_upload: 0,
+            ema_download_kbps: 0.0,
+            ema_upload_kbps: 0.0,
+            last: NetworkStats { country_code: "??".to_string(), ..Default::default() },
+        }
+    }
+}
+
+fn sampler() -> &'static Mutex<Sampler> {
+    static SAMPLER: std::sync::OnceLock<Mutex<Sampler>> = std::sync::OnceLock::new();
+    SAMPLER.get_or_init(|| Mutex::new(Sampler::new()))
+}
+
+/// Set by `request_reset`, consumed by the next tick — the sampler task
+/// owns `sampler()`'s delta/EMA state, so a reset is signalled rather than
+/// applied directly by whatever thread calls `reset_network_session`.
+fn reset_requested() -> &'static AtomicBool {
+    static FLAG: std::sync::OnceLock<AtomicBool> = std::sync::OnceLock::new();
+    FLAG.get_or_init(|| AtomicBool::new(false))
+}
+
+/// Signal the running sampler to clear its session totals and smoothing
+/// state on its next tick, and zero the cached snapshot immediately so a
+/// `get_network_stats` call made in between doesn't read stale numbers.
+pub fn request_reset() {
+    reset_requested().store(true, Ordering::SeqCst);
+    sampler().lock().unwrap().last = Sampler::new().last;
+}
+
+/// The most recently emitted snapshot, for the `get_network_stats` command —
+/// actual sampling now happens exclusively on the background task's own
+/// schedule, not on demand.
+pub fn latest() -> NetworkStats {
+    sampler().lock().unwrap().last.clone()
+}
+
+/// Spawn the sampler task. Runs for the lifetime of the app — unlike
+/// `stats::spawn_poller`, which starts per VPN session, interface byte
+/// counters are meaningful (all zero) whether or not a tunnel is up, so
+/// this starts once from `run()`'s `setup` instead.
+pub fn spawn(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            if reset_requested().swap(false, Ordering::SeqCst) {
+                *sampler().lock().unwrap() = Sampler::new();
+            }
+
+            if let Some((bytes_recv, bytes_sent)) = crate::read_net_counters() {
+                let now = Instant::now();
+                let mut state = sampler().lock().unwrap();
+
+                let (dl_kbps, ul_kbps) = match state.prev {
+                    Some((prev_recv, prev_sent, prev_at)) => {
+                        let elapsed = now.duration_since(prev_at).as_secs_f64();
+                        if elapsed > 0.01 {
+                            let dl_bytes = bytes_recv.saturating_sub(prev_recv);
+                            let ul_bytes = bytes_sent.saturating_sub(prev_sent);
+                            state.session_download += dl_bytes;
+                            state.session_upload += ul_bytes;
+                            ((dl_bytes as f64 / elapsed) / 1024.0, (ul_bytes as f64 / elapsed) / 1024.0)
+                        } else {
+                            (state.ema_download_kbps, state.ema_upload_kbps)
+                        }
+                    }
+                    // First tick — no delta yet, just record the baseline.
+                    None => (0.0, 0.0),
+                };
+
+                state.ema_download_kbps = EMA_ALPHA * dl_kbps + (1.0 - EMA_ALPHA) * state.ema_download_kbps;
+                state.ema_upload_kbps = EMA_ALPHA * ul_kbps + (1.0 - EMA_ALPHA) * state.ema_upload_kbps;
+                state.prev = Some((bytes_recv, bytes_sent, now));
+
+                state.last = NetworkStats {
+                    download_speed: (state.ema_download_kbps * 10.0).round() / 10.0,
+                    upload_speed: (state.ema_upload_kbps * 10.0).round() / 10.0,
+                    total_download: state.session_download,
+                    total_upload: state.session_upload,
+                    country_code: crate::geoip::cached(),
+                };
+
+                let _ = app.emit("network-stats", state.last.clone());
+            }
+
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+        }
+    });
+}
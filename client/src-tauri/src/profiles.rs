@@ -0,0 +1,200 @@
+//! Portable connection profiles: serialize the arguments a connection is
+//! started with (`start_native_vpn`/`start_dnstt`/`start_wireguard`) to and
+//! from a file, instead of making users re-type server/port/credentials
+//! every time. `VpnProfile` is the native, round-trippable format; the
+//! `import_*` functions additionally accept the external formats users are
+//! likely to already have a file for — `.ovpn`, a strongSwan-style
+//! `key=value` parameter file, and an Apple `.mobileconfig` plist — mapping
+//! whatever they contain onto the same fields.
+
+use serde::{Deserialize, Serialize};
+
+/// Bumped only if a field is renamed or removed in a way that breaks
+/// reading an older exported file — new optional fields don't need it.
+const PROFILE_FORMAT_VERSION: u32 = 1;
+
+/// A saveable connection profile. Every field mirrors an argument of
+/// `start_native_vpn`, `start_dnstt`, or `start_wireguard` one-for-one, so
+/// importing a profile is just splatting its fields onto the matching
+/// command and exporting is the reverse.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VpnProfile {
+    #[serde(rename = "formatVersion")]
+    pub format_version: u32,
+    pub name: String,
+    pub protocol: String,
+    pub server: String,
+    pub port: u64,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub psk: String,
+    #[serde(default)]
+    pub auth_method: String,
+    #[serde(default)]
+    pub ike_proposals: Vec<String>,
+    #[serde(default)]
+    pub dnstt_domain: String,
+    #[serde(default)]
+    pub dnstt_public_key: String,
+    #[serde(default)]
+    pub dnstt_resolver: String,
+    #[serde(default)]
+    pub wg_private_key: String,
+    #[serde(default)]
+    pub wg_address: String,
+    #[serde(default)]
+    pub wg_dns: String,
+    #[serde(default)]
+    pub wg_peer_public_key: String,
+    #[serde(default)]
+    pub wg_allowed_ips: String,
+    #[serde(default)]
+    pub wg_persistent_keepalive: u64,
+}
+
+impl VpnProfile {
+    fn new(name: &str, protocol: &str, server: &str, port: u64) -> Self {
+        VpnProfile {
+            format_version: PROFILE_FORMAT_VERSION,
+            name: name.to_string(),
+            protocol: protocol.to_string(),
+            server: server.to_string(),
+            port,
+            ..Default::default()
+        }
+    }
+}
+
+/// Serialize a profile to its portable, re-importable JSON form. The
+/// frontend is responsible for writing the returned string to a
+/// user-chosen `.ccprofile` file — commands here only produce/consume file
+/// *contents*, matching `export_log_events`'s "hand back a string" shape
+/// rather than writing into `app_data_dir` directly, since a profile is the
+/// user's to save wherever they like, not app-owned state.
+pub fn export(profile: &VpnProfile) -> Result<String, String> {
+    serde_json::to_string_pretty(profile).map_err(|e| e.to_string())
+}
+
+/// Parse a previously exported native profile.
+pub fn import_native(contents: &str) -> Result<VpnProfile, String> {
+    serde_json::from_str(contents).map_err(|e| format!("Not a valid CandyConnect profile: {}", e))
+}
+
+/// Pull `directive value` pairs out of an OpenVPN `.ovpn` file, ignoring
+/// comments and the inline `<ca>`/`<cert>`/`<key>` blocks — those carry
+/// material `start_native_openvpn` doesn't take as an argument today, so
+/// they're left for the user to supply via the existing OpenVPN config
+/// upload path rather than silently dropped fields pretending to round-trip.
+pub fn import_ovpn(contents: &str) -> Result<VpnProfile, String> {
+    let mut server = String::new();
+    let mut port: u64 = 1194;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') || line.starts_with('<') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        if fields.next() == Some("remote") {
+            if let Some(host) = fields.next() {
+                server = host.to_string();
+            }
+            if let Some(p) = fields.next() {
+                port = p.parse().unwrap_or(port);
+            }
+        }
+    }
+
+    if server.is_empty() {
+        return Err("No `remote` directive found in .ovpn file".to_string());
+    }
+
+    Ok(VpnProfile::new("Imported OpenVPN profile", "openvpn", &server, port))
+}
+
+/// Parse a strongSwan-style `key = value` parameter file — the same shape
+/// `swanctl.conf` uses, which is what Linux IKEv2/L2TP connections are
+/// already exported in terms of, so importing one from another strongSwan
+/// setup needs no extra translation layer.
+pub fn import_ikev2_params(contents: &str) -> Result<VpnProfile, String> {
+    let mut values = std::collections::HashMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            values.insert(key.trim().to_string(), value.trim().trim_matches('"').to_string());
+        }
+    }
+
+    let server = values.get("remote_addrs").or_else(|| values.get("server")).cloned();
+    let Some(server) = server else {
+        return Err("No `remote_addrs`/`server` key found in IKEv2 parameter file".to_string());
+    };
+    let protocol = values.get("protocol").cloned().unwrap_or_else(|| "ikev2".to_string());
+    let port: u64 = values.get("port").and_then(|p| p.parse().ok()).unwrap_or(4500);
+
+    let mut profile = VpnProfile::new("Imported IKEv2 profile", &protocol, &server, port);
+    profile.username = values.get("username").cloned().unwrap_or_default();
+    profile.password = values.get("password").cloned().unwrap_or_default();
+    profile.psk = values.get("psk").cloned().unwrap_or_default();
+    profile.auth_method = values.get("auth_method").cloned().unwrap_or_else(|| "psk".to_string());
+    if let Some(proposals) = values.get("proposals") {
+        profile.ike_proposals = proposals.split(',').map(|p| p.trim().to_string()).collect();
+    }
+    Ok(profile)
+}
+
+/// Pull the fields out of an Apple `.mobileconfig` IKEv2 plist, reversing
+/// the same `<key>`/`<string>` shape `start_native_vpn`'s macOS branch
+/// builds on export — hand-rolled tag extraction rather than a full plist
+/// parser, since this crate doesn't otherwise depend on one and the
+/// profile's structure here is fixed and known (it's our own output, or a
+/// profile built the same way by another IKEv2 client).
+pub fn import_mobileconfig(contents: &str) -> Result<VpnProfile, String> {
+    let remote_address = plist_string_after(contents, "RemoteAddress")
+        .ok_or("No RemoteAddress found in .mobileconfig")?;
+    let auth_name = plist_string_after(contents, "AuthName").unwrap_or_default();
+    let auth_password = plist_string_after(contents, "AuthPassword").unwrap_or_default();
+    let auth_method = plist_string_after(contents, "AuthenticationMethod").unwrap_or_default();
+
+    let mut profile = VpnProfile::new("Imported IKEv2 profile", "ikev2", &remote_address, 4500);
+    profile.username = auth_name;
+    profile.password = auth_password;
+    profile.auth_method = if auth_method == "Certificate" { "cert".to_string() } else { "psk".to_string() };
+
+    let encryption = plist_string_after(contents, "EncryptionAlgorithm");
+    let integrity = plist_string_after(contents, "IntegrityAlgorithm");
+    let dh_group = plist_int_after(contents, "DiffieHellmanGroup");
+    if let (Some(encryption), Some(integrity), Some(dh_group)) = (encryption, integrity, dh_group) {
+        if let Some(name) = crate::proposal_name_from_macos_params(&encryption, &integrity, dh_group) {
+            profile.ike_proposals = vec![name.to_string()];
+        }
+    }
+
+    Ok(profile)
+}
+
+/// Find `<key>key_name</key>` and return the `<string>` value immediately
+/// following it. Good enough for the flat, single-profile plists this
+/// crate itself generates — nested/multi-payload `.mobileconfig` files
+/// aren't something `start_native_vpn` produces or needs to round-trip.
+fn plist_string_after(contents: &str, key_name: &str) -> Option<String> {
+    let key_tag = format!("<key>{}</key>", key_name);
+    let after_key = &contents[contents.find(&key_tag)? + key_tag.len()..];
+    let start = after_key.find("<string>")? + "<string>".len();
+    let end = after_key.find("</string>")?;
+    Some(after_key[start..end].to_string())
+}
+
+fn plist_int_after(contents: &str, key_name: &str) -> Option<u32> {
+    let key_tag = format!("<key>{}</key>", key_name);
+    let after_key = &contents[contents.find(&key_tag)? + key_tag.len()..];
+    let start = after_key.find("<integer>")? + "<integer>".len();
+    let end = after_key.find("</integer>")?;
+    after_key[start..end].parse().ok()
+}
@@ -0,0 +1,219 @@
+//! Live traffic telemetry for the running Xray/sing-box engines.
+//!
+//! Before this module the engines were black boxes — the only feedback the
+//! frontend had was log lines. `inject_clash_api`/`inject_xray_stats_service`
+//! patch the generated configs (before they're written to disk) to turn on
+//! the management endpoints each tool already ships: sing-box's Clash API
+//! and Xray's StatsService. `spawn_poller` then runs a background thread
+//! that queries those endpoints on an interval and emits `vpn-stats` events,
+//! tied to the same `StopFlag` the process supervisor uses so it stops as
+//! soon as `vpn-disconnected` fires.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::supervisor::StopFlag;
+
+pub const CLASH_API_ADDR: &str = "127.0.0.1:9090";
+pub const XRAY_STATS_API_ADDR: &str = "127.0.0.1:10085";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const QUERY_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// Whether `spawn_poller`'s background loop should keep emitting events.
+/// Toggled by the `set_stats_polling` command independent of the VPN
+/// lifecycle, so the user can pause telemetry without disconnecting.
+fn polling_enabled() -> &'static AtomicBool {
+    static ENABLED: std::sync::OnceLock<AtomicBool> = std::sync::OnceLock::new();
+    ENABLED.get_or_init(|| AtomicBool::new(true))
+}
+
+pub fn set_polling_enabled(enabled: bool) {
+    polling_enabled().store(enabled, Ordering::SeqCst);
+}
+
+/// When the current session started, for `connected_secs` in both the
+/// polling loop and one-shot snapshots. Reset on every `spawn_poller` call.
+fn session_start() -> &'static std::sync::Mutex<Option<Instant>> {
+    static START: std::sync::OnceLock<std::sync::Mutex<Option<Instant>>> = std::sync::OnceLock::new();
+    START.get_or_init(|| std::sync::Mutex::new(None))
+}
+
+/// Add an experimental Clash API listener to a sing-box config JSON value,
+/// bound to loopback only — this is a local telemetry tap, not a feature to
+/// expose on the network.
+pub fn inject_clash_api(config: &mut serde_json::Value, addr: &str) {
+    config["experimental"]["clash_api"] = serde_json::json!({
+        "external_controller": addr,
+    });
+}
+
+/// Enable Xray's StatsService on a parsed xray_config.json value: a loopback
+/// dokodemo-door inbound tagged "api", the api block wiring it to
+/// StatsService, and the policy flags needed for per-inbound/outbound
+/// counters to actually get populated.
+pub fn inject_xray_stats_service(config: &mut serde_json::Value, addr: &str) {
+    let (host, port) = addr.rsplit_once(':').unwrap_or((addr, "10085"));
+    let port: u16 = port.parse().unwrap_or(10085);
+
+    if let Some(inbounds) = config["inbounds"].as_array_mut() {
+        inbounds.push(serde_json::json!({
+            "tag": "api",
+            "listen": host,
+            "port": port,
+            "protocol": "dokodemo-door",
+            "settings": { "address": host },
+        }));
+    }
+
+    config["stats"] = serde_json::json!({});
+    config["api"] = serde_json::json!({
+        "tag": "api",
+        "services": ["StatsService"],
+    });
+    config["policy"]["system"] = serde_json::json!({
+        "statsInboundUplink": true,
+        "statsInboundDownlink": true,
+        "statsOutboundUplink": true,
+        "statsOutboundDownlink": true,
+    });
+}
+
+/// A single traffic snapshot, emitted to the frontend as `vpn-stats`.
+#[derive(serde::Serialize, Clone, Default)]
+pub struct VpnStats {
+    #[serde(rename = "uploadBytes")]
+    pub upload_bytes: u64,
+    #[serde(rename = "downloadBytes")]
+    pub download_bytes: u64,
+    #[serde(rename = "uploadRate")]
+    pub upload_rate: f64,
+    #[serde(rename = "downloadRate")]
+    pub download_rate: f64,
+    #[serde(rename = "activeConnections")]
+    pub active_connections: u64,
+    #[serde(rename = "connectedSecs")]
+    pub connected_secs: u64,
+}
+
+fn http_get_json(addr: &str, path: &str) -> Result<serde_json::Value, String> {
+    let socket_addr = addr
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut a| a.next())
+        .ok_or_else(|| format!("invalid address: {}", addr))?;
+    let mut stream = TcpStream::connect_timeout(&socket_addr, QUERY_TIMEOUT).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(QUERY_TIMEOUT)).ok();
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, addr);
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).map_err(|e| e.to_string())?;
+    let response = String::from_utf8_lossy(&buf);
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or("");
+    serde_json::from_str(body).map_err(|e| format!("bad response from {}{}: {}", addr, path, e))
+}
+
+/// Query sing-box's Clash API for total traffic and active connection count.
+fn query_clash(clash_addr: &str) -> Result<(u64, u64, u64), String> {
+    let body = http_get_json(clash_addr, "/connections")?;
+    let upload = body["uploadTotal"].as_u64().unwrap_or(0);
+    let download = body["downloadTotal"].as_u64().unwrap_or(0);
+    let connections = body["connections"].as_array().map(|a| a.len() as u64).unwrap_or(0);
+    Ok((upload, download, connections))
+}
+
+/// Query Xray's StatsService via the bundled binary's `api statsquery`
+/// subcommand (the same "shell out to the bundled tool" pattern used for
+/// version detection) and sum uplink/downlink counters across all
+/// inbounds/outbounds.
+fn query_xray(xray_bin: &Path, api_addr: &str) -> Result<(u64, u64), String> {
+    let output = Command::new(xray_bin)
+        .args(["api", "statsquery", "--server", api_addr])
+        .output()
+        .map_err(|e| format!("Failed to run `xray api statsquery`: {}", e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let mut up = 0u64;
+    let mut down = 0u64;
+    for line in stdout.lines() {
+        let Some((name_part, value_part)) = line.split_once("value:") else { continue };
+        let Ok(value) = value_part.trim().parse::<u64>() else { continue };
+        if name_part.contains("uplink") {
+            up += value;
+        } else if name_part.contains("downlink") {
+            down += value;
+        }
+    }
+    Ok((up, down))
+}
+
+/// Take a one-shot snapshot for the `get_stats_snapshot` command, using the
+/// session start time recorded by the last `spawn_poller` call (0 if no
+/// session has started yet).
+pub fn snapshot_now(xray_bin: Option<&Path>, clash_addr: Option<&str>, xray_api_addr: &str) -> VpnStats {
+    let connected_since = session_start().lock().unwrap().unwrap_or_else(Instant::now);
+    snapshot(xray_bin, clash_addr, xray_api_addr, connected_since)
+}
+
+/// Take a one-shot traffic snapshot. `clash_addr` is `None` in proxy mode,
+/// where there's no sing-box process to query.
+fn snapshot(xray_bin: Option<&Path>, clash_addr: Option<&str>, xray_api_addr: &str, connected_since: Instant) -> VpnStats {
+    let (clash_up, clash_down, connections) = clash_addr
+        .map(|addr| query_clash(addr).unwrap_or((0, 0, 0)))
+        .unwrap_or((0, 0, 0));
+    let (xray_up, xray_down) = xray_bin
+        .map(|bin| query_xray(bin, xray_api_addr).unwrap_or((0, 0)))
+        .unwrap_or((0, 0));
+
+    VpnStats {
+        upload_bytes: clash_up + xray_up,
+        download_bytes: clash_down + xray_down,
+        upload_rate: 0.0,
+        download_rate: 0.0,
+        active_connections: connections,
+        connected_secs: connected_since.elapsed().as_secs(),
+    }
+}
+
+/// Spawn the background polling task. Runs until `stop_flag` is tripped (a
+/// deliberate disconnect) — it does not watch the child processes itself,
+/// since the existing xray/sing-box watcher threads already trip `stop_flag`
+/// on both deliberate stop and give-up-after-crash paths.
+pub fn spawn_poller(
+    app: tauri::AppHandle,
+    xray_bin: Option<std::path::PathBuf>,
+    clash_addr: Option<String>,
+    xray_api_addr: String,
+    stop_flag: StopFlag,
+) {
+    use tauri::Emitter;
+
+    let connected_since = Instant::now();
+    *session_start().lock().unwrap() = Some(connected_since);
+
+    std::thread::spawn(move || {
+        let mut prev: Option<(u64, u64, Instant)> = None;
+
+        while !stop_flag.is_stopped() {
+            if polling_enabled().load(Ordering::SeqCst) {
+                let mut stats = snapshot(xray_bin.as_deref(), clash_addr.as_deref(), &xray_api_addr, connected_since);
+
+                if let Some((prev_up, prev_down, prev_at)) = prev {
+                    let elapsed = prev_at.elapsed().as_secs_f64();
+                    if elapsed > 0.01 {
+                        stats.upload_rate = stats.upload_bytes.saturating_sub(prev_up) as f64 / elapsed;
+                        stats.download_rate = stats.download_bytes.saturating_sub(prev_down) as f64 / elapsed;
+                    }
+                }
+                prev = Some((stats.upload_bytes, stats.download_bytes, Instant::now()));
+
+                let _ = app.emit("vpn-stats", &stats);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
@@ -0,0 +1,83 @@
+//! Crash-detection and backoff-restart bookkeeping for long-running helper
+//! processes (xray, sing-box).
+//!
+//! A transient crash (server hiccup, ENOMEM, a sing-box panic) shouldn't drop
+//! the whole VPN. `CrashTracker` lets a watcher thread tell an unexpected
+//! exit apart from a user-initiated stop, and `StopFlag` is the signal a
+//! deliberate disconnect uses to short-circuit further restarts.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const STABILITY_WINDOW: Duration = Duration::from_secs(60);
+/// After this many consecutive crashes, stop trying and surface `vpn-failed`.
+pub const MAX_RETRIES: u32 = 8;
+
+/// Delay before the `n`th consecutive restart attempt (0-indexed): doubles
+/// each time starting from `BASE_BACKOFF`, capped at `MAX_BACKOFF`.
+pub fn backoff_delay(n: u32) -> Duration {
+    let millis = (BASE_BACKOFF.as_millis() as u64).saturating_mul(1u64 << n.min(16));
+    Duration::from_millis(millis.min(MAX_BACKOFF.as_millis() as u64))
+}
+
+/// Set by a deliberate disconnect so supervised watcher threads know an exit
+/// was intentional and short-circuit the restart loop. Cheap to clone — all
+/// clones share the same underlying flag.
+#[derive(Clone)]
+pub struct StopFlag(Arc<AtomicBool>);
+
+impl StopFlag {
+    pub fn new() -> Self {
+        StopFlag(Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn request_stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Tracks consecutive-crash state for one supervised process. The counter
+/// resets once the process has stayed up for `STABILITY_WINDOW`, so a
+/// long-running connection that eventually hiccups doesn't inherit a stale
+/// streak and get cut off early.
+pub struct CrashTracker {
+    consecutive_crashes: AtomicU32,
+    last_spawn: Mutex<Option<Instant>>,
+}
+
+impl CrashTracker {
+    pub fn new() -> Self {
+        CrashTracker {
+            consecutive_crashes: AtomicU32::new(0),
+            last_spawn: Mutex::new(None),
+        }
+    }
+
+    /// Call right after a (re)spawn succeeds to start the stability window.
+    pub fn mark_spawned(&self) {
+        *self.last_spawn.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Record an unexpected exit. Returns the attempt number to back off for
+    /// (0-indexed) and whether the streak has now reached `MAX_RETRIES`.
+    pub fn record_crash(&self) -> (u32, bool) {
+        let was_stable = self
+            .last_spawn
+            .lock()
+            .unwrap()
+            .map(|t| t.elapsed() >= STABILITY_WINDOW)
+            .unwrap_or(false);
+        if was_stable {
+            self.consecutive_crashes.store(0, Ordering::SeqCst);
+        }
+        let n = self.consecutive_crashes.fetch_add(1, Ordering::SeqCst);
+        (n, n + 1 >= MAX_RETRIES)
+    }
+}
@@ -0,0 +1,233 @@
+//! Structured, rotated logging pipeline built on `tracing`/`tracing-subscriber`/
+//! `tracing-appender`, replacing `append_log`'s old unbounded hand-rolled
+//! JSON-lines writer.
+//!
+//! The old writer appended a `{timestamp, level, message}` object per line
+//! to `candy.logs` forever — no size bound, no rotation, and no level
+//! filtering on what actually hit disk (only the in-memory ring buffer in
+//! `log_events.rs` was ever filterable). `init` installs a daily-rotating
+//! file appender (capped retention via `MAX_LOG_FILES`) behind a custom
+//! `tracing_subscriber::Layer` that writes the same flat JSON keys the old
+//! file used into rotated `candy.log.*` files; nothing is written to
+//! `candy.logs` any more, and `level` is now upper-case (`INFO`).
+//! The max level is gated at compile time (`DEBUG` in debug builds, `INFO`
+//! in release) so release builds never pay for debug spam they'd never
+//! show the user, and further gated at runtime by `logVerbosity` from
+//! `settings.json` — the same file the pre-existing `language` setting
+//! lives in — via `set_verbosity`, so the user can dial detail up or down
+//! without editing files or restarting. `query` reads the rotated files
+//! back for the `query_logs` command; `log` is what `append_log` now calls
+//! instead of writing the file itself.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Compile-time ceiling — release builds never emit/persist DEBUG-and-below
+/// no matter what `logVerbosity` asks for.
+#[cfg(debug_assertions)]
+const MAX_COMPILED_LEVEL: tracing::Level = tracing::Level::DEBUG;
+#[cfg(not(debug_assertions))]
+const MAX_COMPILED_LEVEL: tracing::Level = tracing::Level::INFO;
+
+/// Rotated files to keep before the oldest is deleted — a couple of weeks
+/// is enough for a support request without the logs directory growing
+/// unbounded the way the single never-rotated file did.
+const MAX_LOG_FILES: usize = 14;
+
+const LOG_FILE_PREFIX: &str = "candy.log";
+
+fn verbosity_floor() -> &'static AtomicU8 {
+    static FLOOR: OnceLock<AtomicU8> = OnceLock::new();
+    FLOOR.get_or_init(|| AtomicU8::new(level_rank(tracing::Level::INFO)))
+}
+
+fn level_rank(level: tracing::Level) -> u8 {
+    match level {
+        tracing::Level::ERROR => 0,
+        tracing::Level::WARN => 1,
+        tracing::Level::INFO => 2,
+        tracing::Level::DEBUG => 3,
+        tracing::Level::TRACE => 4,
+    }
+}
+
+fn parse_level(name: &str) -> tracing::Level {
+    match name.to_lowercase().as_str() {
+        "error" => tracing::Level::ERROR,
+        "warn" | "warning" => tracing::Level::WARN,
+        "debug" => tracing::Level::DEBUG,
+        "trace" => tracing::Level::TRACE,
+        _ => tracing::Level::INFO,
+    }
+}
+
+/// Set the runtime verbosity floor from a `settings.json`-style string
+/// (`"error"`/`"warn"`/`"info"`/`"debug"`/`"trace"`). Called once at
+/// startup with the persisted setting, and again whenever the user changes
+/// it via the `set_log_verbosity` command.
+pub fn set_verbosity(level: &str) {
+    verbosity_floor().store(level_rank(parse_level(level)), Ordering::SeqCst);
+}
+
+fn passes_verbosity(level: tracing::Level) -> bool {
+    level_rank(level) <= verbosity_floor().load(Ordering::SeqCst)
+}
+
+/// Writes one event per line as `{"timestamp", "level", "message"}` — the
+/// same flat shape `append_log` used to write by hand — onto a shared
+/// rotating file appender.
+struct JsonLineLayer {
+    appender: Mutex<tracing_appender::rolling::RollingFileAppender>,
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl tracing::field::Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value).trim_matches('"').to_string();
+        }
+    }
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for JsonLineLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let line = serde_json::json!({
+            "timestamp": chrono::Local::now().to_rfc3339(),
+            "level": event.metadata().level().to_string(),
+            "message": visitor.message,
+        });
+
+        if let Ok(mut appender) = self.appender.lock() {
+            let _ = writeln!(appender, "{}", line);
+        }
+    }
+}
+
+/// Build the rotating-file `tracing` pipeline and install it as the global
+/// default subscriber. Called once from `run()`'s `setup`.
+pub fn init(app_data_dir: &Path, initial_verbosity: &str) {
+    set_verbosity(initial_verbosity);
+
+    let appender = tracing_appender::rolling::Builder::new()
+        .rotation(tracing_appender::rolling::Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .max_log_files(MAX_LOG_FILES)
+        .build(app_data_dir)
+        .expect("Failed to initialize rotating log appender");
+
+    let subscriber = tracing_subscriber::registry()
+        .with(JsonLineLayer { appender: Mutex::new(appender) })
+        .with(tracing_subscriber::filter::LevelFilter::from_level(MAX_COMPILED_LEVEL));
+
+    // Not fatal if a subscriber is already installed — just means this
+    // call's verbosity/appender lost the race, which only matters if
+    // `init` is ever called twice.
+    let _ = tracing::subscriber::set_global_default(subscriber);
+}
+
+/// Record one log line: persisted through the rotating pipeline (subject
+/// to both the compiled-in max level and the runtime verbosity floor) and,
+/// unconditionally, through `log_events::record` so the in-memory ring
+/// buffer and `log-event` stream keep seeing everything regardless of the
+/// on-disk verbosity setting.
+pub fn log(level: &str, message: &str) {
+    let parsed = parse_level(level);
+    if passes_verbosity(parsed) {
+        match parsed {
+            tracing::Level::ERROR => tracing::error!("{}", message),
+            tracing::Level::WARN => tracing::warn!("{}", message),
+            tracing::Level::INFO => tracing::info!("{}", message),
+            tracing::Level::DEBUG => tracing::debug!("{}", message),
+            tracing::Level::TRACE => tracing::trace!("{}", message),
+        }
+    }
+
+    crate::log_events::record(level, message);
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PersistedLogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub message: String,
+}
+
+/// Read back persisted log entries across every rotated `candy.log.*` file
+/// in `app_data_dir`, filtered by exact `level`, a case-insensitive
+/// `contains` substring of `message`, and/or a half-open `[since, until)`
+/// RFC3339 timestamp range, then capped to the most recent `limit`
+/// matches. Files are read oldest-to-newest first so the cap keeps the
+/// most recent entries across file boundaries, not just within the
+/// newest file.
+pub fn query(
+    app_data_dir: &Path,
+    level: Option<&str>,
+    contains: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+    limit: Option<usize>,
+) -> Vec<PersistedLogEntry> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(app_data_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| {
+                    p.file_name()
+                        .and_then(|n| n.to_str())
+                        .is_some_and(|n| n.starts_with(LOG_FILE_PREFIX))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+
+    let contains_lower = contains.map(|s| s.to_lowercase());
+    let mut matches = Vec::new();
+
+    for file in files {
+        let Ok(content) = std::fs::read_to_string(&file) else { continue };
+        for line in content.lines() {
+            let Ok(entry) = serde_json::from_str::<PersistedLogEntry>(line) else { continue };
+
+            if let Some(want_level) = level {
+                if !entry.level.eq_ignore_ascii_case(want_level) {
+                    continue;
+                }
+            }
+            if let Some(needle) = &contains_lower {
+                if !entry.message.to_lowercase().contains(needle.as_str()) {
+                    continue;
+                }
+            }
+            if let Some(since) = since {
+                if entry.timestamp.as_str() < since {
+                    continue;
+                }
+            }
+            if let Some(until) = until {
+                if entry.timestamp.as_str() >= until {
+                    continue;
+                }
+            }
+            matches.push(entry);
+        }
+    }
+
+    match limit {
+        Some(n) if n < matches.len() => matches.split_off(matches.len() - n),
+        _ => matches,
+    }
+}
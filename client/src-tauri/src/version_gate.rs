@@ -0,0 +1,117 @@
+//! Pre-flight binary-version compatibility gate for the bundled xray/sing-box
+//! companions.
+//!
+//! `spawn_sing_box_process` unconditionally sets deprecated-feature env vars
+//! (`ENABLE_DEPRECATED_WIREGUARD_OUTBOUND` and friends) that only exist on a
+//! narrow range of sing-box releases — outside that range the flags are
+//! silently ignored (older builds) or the feature they gate has been removed
+//! outright (newer builds), and the child exits immediately with a cryptic
+//! status instead of a useful error. `check_compatibility` runs `<bin>
+//! version`, parses the reported semver, and checks it against a bundled
+//! min/max table before any config is written or process spawned, so a
+//! mismatch surfaces as an actionable error instead.
+//!
+//! Results are cached per binary path + mtime so a normal connect doesn't
+//! re-spawn `version` every time.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binary {
+    Xray,
+    SingBox,
+}
+
+impl Binary {
+    fn name(self) -> &'static str {
+        match self {
+            Binary::Xray => "Xray",
+            Binary::SingBox => "Sing-box",
+        }
+    }
+
+    /// Inclusive (min, max) compatible version range for this binary, chosen
+    /// to match the deprecated-flag env vars `spawn_sing_box_process` sets.
+    /// `max` is `None` when there's no known upper bound yet.
+    fn compatible_range(self) -> ((u32, u32, u32), Option<(u32, u32, u32)>) {
+        match self {
+            Binary::Xray => ((1, 8, 0), None),
+            Binary::SingBox => ((1, 8, 0), Some((1, 10, 99))),
+        }
+    }
+}
+
+fn cache() -> &'static Mutex<HashMap<(std::path::PathBuf, Option<SystemTime>), String>> {
+    static CACHE: std::sync::OnceLock<Mutex<HashMap<(std::path::PathBuf, Option<SystemTime>), String>>> =
+        std::sync::OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn parse_semver(raw: &str) -> Option<(u32, u32, u32)> {
+    // `xray version` / `sing-box version` both print something like
+    // "Xray 1.8.4 (...)" / "sing-box version 1.9.3" on the first line —
+    // scan for the first dotted-number token rather than anchoring on a
+    // fixed prefix so we're resilient to wording differences between tools.
+    raw.split_whitespace().find_map(|tok| {
+        let tok = tok.trim_start_matches('v');
+        let mut parts = tok.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next().unwrap_or("0");
+        let patch: u32 = patch.chars().take_while(|c| c.is_ascii_digit()).collect::<String>().parse().ok()?;
+        Some((major, minor, patch))
+    })
+}
+
+fn detect_version(bin: &Path) -> Result<String, String> {
+    let output = Command::new(bin)
+        .arg("version")
+        .output()
+        .map_err(|e| format!("Failed to run `{} version`: {}", bin.display(), e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first_line = stdout.lines().next().unwrap_or("").trim().to_string();
+    if first_line.is_empty() {
+        return Err(format!("`{} version` produced no output", bin.display()));
+    }
+    Ok(first_line)
+}
+
+/// Run `<bin> version`, parse the reported semver, and check it against
+/// `binary`'s compatible range. Returns the raw version string (for logging)
+/// on success. Cached per binary path + mtime.
+pub fn check_compatibility(binary: Binary, bin: &Path) -> Result<String, String> {
+    let mtime = bin.metadata().and_then(|m| m.modified()).ok();
+    let key = (bin.to_path_buf(), mtime);
+
+    if let Some(cached) = cache().lock().unwrap().get(&key) {
+        return Ok(cached.clone());
+    }
+
+    let raw_version = detect_version(bin)?;
+    let (major, minor, patch) = parse_semver(&raw_version)
+        .ok_or_else(|| format!("Could not parse a version number out of: \"{}\"", raw_version))?;
+
+    let (min, max) = binary.compatible_range();
+    if (major, minor, patch) < min {
+        return Err(format!(
+            "{} {}.{}.{} is too old; bundled config requires >= {}.{}.{}",
+            binary.name(), major, minor, patch, min.0, min.1, min.2
+        ));
+    }
+    if let Some(max) = max {
+        if (major, minor, patch) > max {
+            return Err(format!(
+                "{} {}.{}.{} removed features the bundled config relies on; \
+                 bundled config requires <= {}.{}.{}",
+                binary.name(), major, minor, patch, max.0, max.1, max.2
+            ));
+        }
+    }
+
+    cache().lock().unwrap().insert(key, raw_version.clone());
+    Ok(raw_version)
+}
@@ -0,0 +1,298 @@
+//! Minimal client for charon's VICI unix-socket protocol.
+//!
+//! The Linux IKEv2/L2TP driver used to infer tunnel health by grepping
+//! `nmcli connection show --active`, which only reflects NetworkManager's
+//! view of a connection it doesn't actually own once we're driving charon
+//! directly. VICI is charon's own RPC socket — every packet is a 4-byte
+//! big-endian length prefix followed by a type byte and a stream of
+//! key/value, section and list elements. Only enough of that is
+//! implemented here to ask "is an SA up, and how big is it" (`list-sas`);
+//! connection setup itself still goes through `swanctl`.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// Default location charon's vici plugin listens on.
+pub const VICI_SOCKET: &str = "/run/ipsec/charon.vici";
+
+const PKT_CMD_REQUEST: u8 = 0;
+const PKT_CMD_RESPONSE: u8 = 1;
+
+const ELEM_KEY_VALUE: u8 = 2;
+const ELEM_SECTION_START: u8 = 3;
+const ELEM_SECTION_END: u8 = 4;
+const ELEM_LIST_START: u8 = 5;
+const ELEM_LIST_ITEM: u8 = 6;
+const ELEM_LIST_END: u8 = 7;
+
+/// A decoded VICI response, flattened to dotted keys (e.g.
+/// `candyconnect.bytes-in`) since nested sections are all this driver ever
+/// reads — never a reason to model them as a tree here.
+pub type ViciMessage = HashMap<String, String>;
+
+pub struct ViciClient {
+    stream: UnixStream,
+}
+
+impl ViciClient {
+    pub fn connect(socket_path: &Path) -> std::io::Result<Self> {
+        let stream = UnixStream::connect(socket_path)?;
+        stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+        Ok(ViciClient { stream })
+    }
+
+    /// Send a named command with no request payload (e.g. `list-sas`,
+    /// `stats`) and decode the single response packet that comes back.
+    pub fn request(&mut self, command: &str) -> std::io::Result<ViciMessage> {
+        let mut packet = Vec::with_capacity(command.len() + 2);
+        packet.push(PKT_CMD_REQUEST);
+        packet.push(command.len() as u8);
+        packet.extend_from_slice(command.as_bytes());
+        self.write_packet(&packet)?;
+
+        let response = self.read_packet()?;
+        if response.first() != Some(&PKT_CMD_RESPONSE) {
+            return Ok(HashMap::new());
+        }
+        Ok(decode_elements(&response[1..]))
+    }
+
+    fn write_packet(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        self.stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+        self.stream.write_all(payload)
+    }
+
+    fn read_packet(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut len_buf = [0u8; 4];
+        self.stream.read_exact(&mut len_buf)?;
+        let len = u32::from_be_bytes(len_buf) as usize;
+        let mut buf = vec![0u8; len];
+        self.stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Walk a flat element stream, tracking the current section nesting so
+/// keys can be flattened to `section.subsection.key`. Lists are joined with
+/// commas rather than kept as a separate shape — every list this driver
+/// reads (SA child names, proposal strings) is fine as one string.
+fn decode_elements(buf: &[u8]) -> ViciMessage {
+    let mut out = HashMap::new();
+    let mut sections: Vec<String> = Vec::new();
+    let mut pending_list_key: Option<String> = None;
+    let mut pending_list_items: Vec<String> = Vec::new();
+    let mut pos = 0;
+
+    while pos < buf.len() {
+        let elem_type = buf[pos];
+        pos += 1;
+
+        match elem_type {
+            ELEM_SECTION_START => {
+                let Some((name, next)) = read_short_string(buf, pos) else { break };
+                sections.push(name);
+                pos = next;
+            }
+            ELEM_SECTION_END => {
+                sections.pop();
+            }
+            ELEM_KEY_VALUE => {
+                let Some((key, next)) = read_short_string(buf, pos) else { break };
+                let Some((value, next2)) = read_long_string(buf, next) else { break };
+                out.insert(dotted_key(&sections, &key), value);
+                pos = next2;
+            }
+            ELEM_LIST_START => {
+                let Some((name, next)) = read_short_string(buf, pos) else { break };
+                pending_list_key = Some(dotted_key(&sections, &name));
+                pending_list_items.clear();
+                pos = next;
+            }
+            ELEM_LIST_ITEM => {
+                let Some((value, next)) = read_long_string(buf, pos) else { break };
+                pending_list_items.push(value);
+                pos = next;
+            }
+            ELEM_LIST_END => {
+                if let Some(key) = pending_list_key.take() {
+                    out.insert(key, pending_list_items.join(","));
+                }
+            }
+            _ => break,
+        }
+    }
+
+    out
+}
+
+fn dotted_key(sections: &[String], key: &str) -> String {
+    if sections.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", sections.join("."), key)
+    }
+}
+
+/// Names and keys are length-prefixed with a single byte.
+fn read_short_string(buf: &[u8], pos: usize) -> Option<(String, usize)> {
+    let len = *buf.get(pos)? as usize;
+    let start = pos + 1;
+    let end = start + len;
+    Some((String::from_utf8_lossy(buf.get(start..end)?).to_string(), end))
+}
+
+/// Values are length-prefixed with a 2-byte big-endian length.
+fn read_long_string(buf: &[u8], pos: usize) -> Option<(String, usize)> {
+    let len = u16::from_be_bytes(buf.get(pos..pos + 2)?.try_into().ok()?) as usize;
+    let start = pos + 2;
+    let end = start + len;
+    Some((String::from_utf8_lossy(buf.get(start..end)?).to_string(), end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Byte-level builder for the element types `decode_elements` consumes,
+    /// so each test reads as the wire shape it's asserting on instead of a
+    /// wall of magic bytes.
+    struct ElementsBuilder(Vec<u8>);
+
+    impl ElementsBuilder {
+        fn new() -> Self {
+            ElementsBuilder(Vec::new())
+        }
+
+        fn short_string(&mut self, s: &str) {
+            self.0.push(s.len() as u8);
+            self.0.extend_from_slice(s.as_bytes());
+        }
+
+        fn long_string(&mut self, s: &str) {
+            self.0.extend_from_slice(&(s.len() as u16).to_be_bytes());
+            self.0.extend_from_slice(s.as_bytes());
+        }
+
+        fn key_value(mut self, key: &str, value: &str) -> Self {
+            self.0.push(ELEM_KEY_VALUE);
+            self.short_string(key);
+            self.long_string(value);
+            self
+        }
+
+        fn section_start(mut self, name: &str) -> Self {
+            self.0.push(ELEM_SECTION_START);
+            self.short_string(name);
+            self
+        }
+
+        fn section_end(mut self) -> Self {
+            self.0.push(ELEM_SECTION_END);
+            self
+        }
+
+        fn list_start(mut self, name: &str) -> Self {
+            self.0.push(ELEM_LIST_START);
+            self.short_string(name);
+            self
+        }
+
+        fn list_item(mut self, value: &str) -> Self {
+            self.0.push(ELEM_LIST_ITEM);
+            self.long_string(value);
+            self
+        }
+
+        fn list_end(mut self) -> Self {
+            self.0.push(ELEM_LIST_END);
+            self
+        }
+
+        fn build(self) -> Vec<u8> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn decodes_top_level_key_value() {
+        let buf = ElementsBuilder::new().key_value("uptime", "42").build();
+        let msg = decode_elements(&buf);
+        assert_eq!(msg.get("uptime"), Some(&"42".to_string()));
+    }
+
+    #[test]
+    fn flattens_nested_sections_to_dotted_keys() {
+        let buf = ElementsBuilder::new()
+            .section_start("candyconnect")
+            .key_value("bytes-in", "1024")
+            .section_start("local-id")
+            .key_value("type", "key-id")
+            .section_end()
+            .section_end()
+            .build();
+        let msg = decode_elements(&buf);
+        assert_eq!(msg.get("candyconnect.bytes-in"), Some(&"1024".to_string()));
+        assert_eq!(msg.get("candyconnect.local-id.type"), Some(&"key-id".to_string()));
+    }
+
+    #[test]
+    fn sibling_sections_do_not_leak_into_each_other() {
+        let buf = ElementsBuilder::new()
+            .section_start("sa-a")
+            .key_value("state", "established")
+            .section_end()
+            .section_start("sa-b")
+            .key_value("state", "connecting")
+            .section_end()
+            .build();
+        let msg = decode_elements(&buf);
+        assert_eq!(msg.get("sa-a.state"), Some(&"established".to_string()));
+        assert_eq!(msg.get("sa-b.state"), Some(&"connecting".to_string()));
+    }
+
+    #[test]
+    fn joins_list_items_with_commas() {
+        let buf = ElementsBuilder::new()
+            .list_start("child-sas")
+            .list_item("candyconnect")
+            .list_item("candyconnect-l2tp")
+            .list_end()
+            .build();
+        let msg = decode_elements(&buf);
+        assert_eq!(msg.get("child-sas"), Some(&"candyconnect,candyconnect-l2tp".to_string()));
+    }
+
+    #[test]
+    fn list_inside_a_section_keeps_its_dotted_prefix() {
+        let buf = ElementsBuilder::new()
+            .section_start("candyconnect")
+            .list_start("proposals")
+            .list_item("aes128-sha256-modp3072")
+            .list_end()
+            .section_end()
+            .build();
+        let msg = decode_elements(&buf);
+        assert_eq!(msg.get("candyconnect.proposals"), Some(&"aes128-sha256-modp3072".to_string()));
+    }
+
+    #[test]
+    fn truncated_buffer_stops_instead_of_panicking() {
+        // A key-value element whose value length prefix claims more bytes
+        // than are actually present — `decode_elements` should bail out via
+        // its `else { break }` arms rather than panic on an out-of-bounds
+        // slice.
+        let mut buf = ElementsBuilder::new().key_value("ok", "yes").build();
+        buf.push(ELEM_KEY_VALUE);
+        buf.push(3);
+        buf.extend_from_slice(b"key");
+        buf.extend_from_slice(&100u16.to_be_bytes()); // claims 100 bytes, none follow
+
+        let msg = decode_elements(&buf);
+        assert_eq!(msg.get("ok"), Some(&"yes".to_string()));
+        assert_eq!(msg.len(), 1);
+    }
+}